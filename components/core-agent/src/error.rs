@@ -0,0 +1,42 @@
+// External crates
+use thiserror::Error;
+
+/// Crate-wide error type. Every variant is a category of failure of the Core Agent, so
+/// callers, e.g., the runtime's supervisor, can tell what failed and react to it without
+/// inspecting error messages. The underlying cause is kept as an `anyhow::Error` with its
+/// full context chain.
+#[derive(Debug, Error)]
+pub enum VesError {
+    /// The configuration couldn't be loaded or is invalid
+    #[error("config error: {0:#}")]
+    Config(anyhow::Error),
+
+    /// A `Watcher` failed to watch or discover data files
+    #[error("watch error: {0:#}")]
+    Watch(anyhow::Error),
+
+    /// A `Tailer` failed to read its data file
+    #[error("tail error: {0:#}")]
+    Tail(anyhow::Error),
+
+    /// The `Checkpoint` couldn't be recovered or persisted
+    #[error("checkpoint error: {0:#}")]
+    Checkpoint(anyhow::Error),
+
+    /// The runtime failed to start or keep the pipeline running
+    #[error("runtime error: {0:#}")]
+    Runtime(anyhow::Error),
+}
+
+impl VesError {
+    /// Short, stable name of the error's category, for logs and metrics labels
+    pub fn category(&self) -> &'static str {
+        match self {
+            VesError::Config(_) => "config",
+            VesError::Watch(_) => "watch",
+            VesError::Tail(_) => "tail",
+            VesError::Checkpoint(_) => "checkpoint",
+            VesError::Runtime(_) => "runtime",
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// External crates
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Default location of the Core Agent's config file
+const DEFAULT_CONFIG_PATH: &str = "/etc/ves/config.toml";
+
+/// Command line interface of the Core Agent
+#[derive(Debug, Parser)]
+#[command(name = "ves", version, about = "The VES platform's Core Agent")]
+pub struct Cli {
+    /// Path to the Core Agent's TOML config file
+    #[arg(long, short, default_value = DEFAULT_CONFIG_PATH)]
+    pub config: PathBuf,
+
+    /// Override a config value, e.g., `--set watcher.recursive=false`. Overrides take
+    /// precedence over the config file and `VES_*` environment variables
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// Validate the config and print which data files would be tailed and how much of
+    /// them is unread, then exit without starting the pipeline
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Replay filesystem events recorded with `watcher.record_events_path` through the
+    /// Watchers' event translation and the TailerManager's event handling, printing
+    /// every step, then exit
+    #[arg(long, value_name = "RECORDING")]
+    pub replay: Option<PathBuf>,
+}
@@ -0,0 +1,108 @@
+// Local crates
+use crate::helpers::load_config::Config;
+
+// External crates
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Time to let a burst of filesystem events settle before the config file is read, an
+/// editor saving a file usually causes several
+const CONFIG_CHANGE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch the config file at `path` until `cancel` is cancelled and revalidate it every
+/// time it changes, layered with the same `overrides` the Core Agent was started with.
+///
+/// Each change is reported against `running`, the configuration the Core Agent is
+/// running with: whether the new file is valid, which sections changed, and that a
+/// restart is required to apply them, as no section is reloaded at runtime yet.
+///
+/// The config file's directory is watched rather than the file itself, so the file
+/// being replaced, e.g., by an editor's atomic save, is noticed. Any change in the
+/// directory re-reads the config file and only a change of its contents is reported,
+/// as a ConfigMap update swaps the `..data` symlink the file points through and no
+/// event names the file itself.
+pub async fn watch_config(
+    path: PathBuf,
+    overrides: Vec<String>,
+    running: Config,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let (fs_tx, mut fs_rx) = mpsc::channel::<Event>(128);
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res| {
+            if let Ok(event) = res {
+                let _ = fs_tx.blocking_send(event);
+            }
+        },
+        notify::Config::default(),
+    )?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("watching config directory {}", dir.display()))?;
+
+    let mut contents = tokio::fs::read(&path).await.ok();
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+
+            Some(event) = fs_rx.recv() => {
+                // reading the config file below is an access of its own
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                sleep(CONFIG_CHANGE_DEBOUNCE).await;
+                while fs_rx.try_recv().is_ok() {}
+
+                let current = tokio::fs::read(&path).await.ok();
+                if current == contents {
+                    continue;
+                }
+
+                contents = current;
+                revalidate(&path, &overrides, &running);
+            }
+        }
+    }
+}
+
+/// Load the changed config file and log a validation report for it
+fn revalidate(path: &Path, overrides: &[String], running: &Config) {
+    let config = match Config::load_layered(path, overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(
+                path = %path.display(),
+                error = %e,
+                "Config file changed and is invalid, a restart would fail"
+            );
+            return;
+        }
+    };
+
+    let changed = running.changed_sections(&config);
+
+    if changed.is_empty() {
+        info!(path = %path.display(), "Config file changed, effective config is unchanged");
+        return;
+    }
+
+    warn!(
+        path = %path.display(),
+        sections = ?changed,
+        "Config file changed and is valid, restart required to apply the changed sections"
+    );
+}
@@ -0,0 +1,103 @@
+// Local crates
+use crate::{
+    helpers::load_config::Config,
+    watcher::{
+        discovery::{excluded_by_policy, walk_data_files},
+        models::FileId,
+        store::{FileCheckpointStore, checkpoint_path},
+        watcher::split_by_root,
+    },
+};
+
+// External crates
+use anyhow::Result;
+use std::os::unix::fs::MetadataExt;
+
+/// What the Core Agent would pick up under a single watched directory
+#[derive(Debug, Default)]
+struct RootSummary {
+    files: usize,
+    new_files: usize,
+    skipped_files: usize,
+    bytes: u64,
+    pending_bytes: u64,
+}
+
+/// Run discovery against `config` without starting the pipeline and print what the Core
+/// Agent would tail: per watched directory, how many data files match, how many of them
+/// the checkpoint doesn't know yet, how many are skipped by the age and size limits, and
+/// how many bytes are still unread. Nothing is
+/// written, neither the checkpoint nor any directory, so this is safe to run next to a
+/// live Core Agent to validate a config change.
+pub fn run(config: &Config) -> Result<()> {
+    let path = checkpoint_path(&config.watcher);
+    let checkpoint = FileCheckpointStore::new(path.clone()).read()?;
+
+    println!("checkpoint: {} ({} entries)", path.display(), checkpoint.files.len());
+
+    let mut total = RootSummary::default();
+
+    for (root, root_checkpoint) in split_by_root(&config.watcher, checkpoint)? {
+        let mut summary = RootSummary::default();
+
+        for entry in walk_data_files(&root)? {
+            // metadata follows symlinks, like the Watcher identifying data files
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                continue;
+            }
+
+            let id = FileId {
+                dev: metadata.dev(),
+                inode: metadata.ino(),
+            };
+
+            let offset = match root_checkpoint.files.get(&id) {
+                Some(state) if !state.skipped => state.offset,
+                _ if excluded_by_policy(&root, &metadata).is_some() => {
+                    summary.files += 1;
+                    summary.skipped_files += 1;
+                    continue;
+                }
+                // a skipped data file the limits no longer exclude, tailed from where it
+                // was skipped unless backfilling
+                Some(state) if !root.backfill.unwrap_or(false) => state.offset,
+                Some(_) => 0,
+                None => {
+                    summary.new_files += 1;
+                    0
+                }
+            };
+
+            summary.files += 1;
+            summary.bytes += metadata.len();
+            summary.pending_bytes += metadata.len().saturating_sub(offset);
+        }
+
+        println!(
+            "{}: {} data files ({} new, {} skipped), {} bytes, {} bytes unread",
+            root.log_dir,
+            summary.files,
+            summary.new_files,
+            summary.skipped_files,
+            summary.bytes,
+            summary.pending_bytes
+        );
+
+        total.files += summary.files;
+        total.new_files += summary.new_files;
+        total.skipped_files += summary.skipped_files;
+        total.bytes += summary.bytes;
+        total.pending_bytes += summary.pending_bytes;
+    }
+
+    println!(
+        "total: {} data files ({} new, {} skipped), {} bytes, {} bytes unread",
+        total.files, total.new_files, total.skipped_files, total.bytes, total.pending_bytes
+    );
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+// External crates
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+
+/// Exclusive lock making this Core Agent the leader among instances sharing the same lock
+/// file, e.g., on a shared volume. Only the leader tails data files and writes the
+/// checkpoint. Leadership is released when the `LeaderLock` is dropped, or by the OS when
+/// the leader dies, so a follower polling [`LeaderLock::try_acquire`] takes over quickly.
+#[derive(Debug)]
+pub struct LeaderLock {
+    _file: File,
+}
+
+impl LeaderLock {
+    /// Try to become the leader without blocking. Returns `None` while another instance
+    /// holds the lock. The leader writes its process ID into the lock file so operators
+    /// can tell which instance is leading.
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("opening leader lock {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("locking {}", path.display()));
+            }
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::ensure;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Lock file of a single test, removed when the test is done
+    struct TestLock(PathBuf);
+
+    impl TestLock {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("ves-{name}-{}.lock", std::process::id())))
+        }
+    }
+
+    impl Drop for TestLock {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn only_one_instance_leads_at_a_time() -> Result<()> {
+        let lock = TestLock::new("leader-exclusive");
+
+        let leader = LeaderLock::try_acquire(&lock.0)?;
+        ensure!(leader.is_some(), "expected the first instance to lead");
+
+        let follower = LeaderLock::try_acquire(&lock.0)?;
+        ensure!(follower.is_none(), "expected a second instance to stand by");
+
+        drop(leader);
+
+        let successor = LeaderLock::try_acquire(&lock.0)?;
+        ensure!(successor.is_some(), "expected leadership to be free once released");
+
+        Ok(())
+    }
+
+    #[test]
+    fn leader_writes_its_process_id() -> Result<()> {
+        let lock = TestLock::new("leader-pid");
+        fs::write(&lock.0, "a much longer leftover from a previous leader\n")?;
+
+        let _leader = LeaderLock::try_acquire(&lock.0)?;
+
+        let contents = fs::read_to_string(&lock.0)?;
+        ensure!(
+            contents == format!("{}\n", std::process::id()),
+            "expected only this process ID in the lock file, got {contents:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unopenable_lock_file_is_an_error() -> Result<()> {
+        let result = LeaderLock::try_acquire(Path::new("/nonexistent/ves/leader.lock"));
+
+        ensure!(result.is_err(), "expected a missing directory to be an error, got {result:?}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,728 @@
+// External crates
+use anyhow::{Context, Result, anyhow};
+use regex::{Captures, Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// `${VAR}` or `${VAR:-default}` inside a config string value, or either escaped as
+/// `$${VAR}`
+// the pattern is a constant, it either compiles on every run or on none
+#[allow(clippy::expect_used)]
+static ENV_VAR_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}")
+        .expect("ENV_VAR_PATTERN is a valid regex")
+});
+
+/// Prefix of a config string value that is read from a file, e.g., a mounted secret
+const FILE_VALUE_PREFIX: &str = "file:";
+
+/// Prefix of environment variables that override config values
+const ENV_OVERRIDE_PREFIX: &str = "VES_";
+
+/// Separator between nested config keys in environment variable overrides
+const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
+/// Core Agent configuration, loaded from its TOML config file with [`Config::load`]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub watcher: WatcherConfig,
+    #[serde(default)]
+    pub tailer: TailerConfig,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+}
+
+impl Config {
+    /// Load the configuration from the TOML file at `path`, with overrides from the
+    /// environment applied. See [`Config::load_layered`].
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_layered(path, &[])
+    }
+
+    /// Load the configuration from the TOML file at `path` and layer overrides on top of
+    /// it. Later layers take precedence over earlier ones:
+    ///
+    /// 1. Defaults of optional values
+    /// 2. The config file
+    /// 3. Environment variables, `VES_<SECTION>__<KEY>=value`, e.g.,
+    ///    `VES_TAILER__MAX_OPEN_FILES=4096`. Nested keys are separated by `__`
+    /// 4. CLI overrides, `<section>.<key>=value`, e.g., `watcher.recursive=false`
+    ///
+    /// Override values are read as TOML values (numbers, booleans, arrays), anything
+    /// that doesn't parse as one is taken as a string.
+    /// Overrides can only set keys of the config sections, and a value that doesn't fit
+    /// its key is reported with the variable or flag that set it.
+    ///
+    /// Every string value is interpolated after layering, so secrets don't have to be
+    /// written into the config file itself:
+    /// - `${VAR}` is replaced by the environment variable `VAR`, `${VAR:-default}` falls
+    ///   back to `default` when `VAR` is unset. An unset variable without a default is an error.
+    ///   `$${VAR}` is kept as a literal `${VAR}`.
+    /// - A value of `file:/path/to/secret` is replaced by the contents of that file, with
+    ///   trailing newlines trimmed. Environment variables are interpolated first, so
+    ///   `file:${SECRETS_DIR}/token` works.
+    pub fn load_layered(path: &Path, cli_overrides: &[String]) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+
+        // vars() panics on variables that aren't valid unicode, those can't be overrides anyway
+        let env_vars: Vec<(String, String)> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+
+        Self::from_layers(path, &raw, &env_vars, cli_overrides)
+    }
+
+    /// Layer `env_vars` and `cli_overrides` over `raw`, the contents of the config file at
+    /// `path`, see [`Config::load_layered`]. Every override is checked as it is applied,
+    /// so a bad value is reported with the variable or flag that set it.
+    fn from_layers(
+        path: &Path,
+        raw: &str,
+        env_vars: &[(String, String)],
+        cli_overrides: &[String],
+    ) -> Result<Self> {
+        let mut value: toml::Value = toml::from_str(raw)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+
+        for (name, raw_value) in env_vars {
+            let Some(key) = name.strip_prefix(ENV_OVERRIDE_PREFIX) else {
+                continue;
+            };
+
+            let key_path: Vec<String> = key
+                .split(ENV_OVERRIDE_SEPARATOR)
+                .map(str::to_lowercase)
+                .collect();
+
+            apply_override(&mut value, &key_path, raw_value)
+                .with_context(|| format!("applying environment override {name}"))?;
+        }
+
+        for cli_override in cli_overrides {
+            let (key, raw_value) = cli_override
+                .split_once('=')
+                .ok_or_else(|| anyhow!("config override {cli_override} is not KEY=VALUE"))?;
+
+            let key_path: Vec<String> = key.split('.').map(str::to_string).collect();
+
+            apply_override(&mut value, &key_path, raw_value)
+                .with_context(|| format!("applying config override --set {key}"))?;
+        }
+
+        interpolate(&mut value, env_vars)?;
+
+        value
+            .try_into()
+            .with_context(|| format!("invalid config in {}", path.display()))
+    }
+
+    /// Names of the top-level sections that differ between `self` and `other`
+    pub fn changed_sections(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        if self.watcher != other.watcher {
+            changed.push("watcher");
+        }
+
+        if self.tailer != other.tailer {
+            changed.push("tailer");
+        }
+
+        if self.runtime != other.runtime {
+            changed.push("runtime");
+        }
+
+        changed
+    }
+}
+
+/// Configuration for a `Watcher`, i.e., the *log_dir* it watches for data files and
+/// how it keeps track of them in its `Checkpoint`.
+///
+/// Optional values fall back to their defaults where they are used.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatcherConfig {
+    /// Directory to watch for data files, or a single data file to watch
+    pub log_dir: String,
+    pub recursive: Option<bool>,
+    /// How many directory levels below *log_dir* a recursive Watcher descends, data files
+    /// directly in *log_dir* are at depth 1. Unlimited when unset
+    pub max_depth: Option<usize>,
+    /// Regexes matched against file names, replacing the default `.log`/`.txt` filter
+    pub include: Option<Vec<String>>,
+    /// Skip newly discovered data files not modified for this many days
+    pub ignore_older_than_days: Option<u64>,
+    /// Skip newly discovered data files larger than this many bytes
+    pub ignore_larger_than_bytes: Option<u64>,
+    /// Pick up every data file regardless of *ignore_older_than_days* and
+    /// *ignore_larger_than_bytes*, e.g., to backfill history once
+    pub backfill: Option<bool>,
+    /// Additional directories watched alongside *log_dir*
+    pub watch_dirs: Option<Vec<WatchRoot>>,
+    /// Follow symlinked data files and directories, e.g., Kubernetes' `/var/log/containers`
+    pub follow_symlinks: Option<bool>,
+    /// Seconds a `Checkpoint` entry is kept after its data file disappeared
+    pub checkpoint_ttl_secs: Option<u64>,
+    /// Upper bound on `Checkpoint` entries before stale entries are compacted
+    pub checkpoint_max_entries: Option<usize>,
+    /// File the `Checkpoint` is persisted to, defaults to `$XDG_STATE_HOME/ves/checkpoint.json`
+    /// or `/var/lib/ves/checkpoint.json`
+    pub checkpoint_path: Option<String>,
+    /// Milliseconds between batched `Checkpoint` writes
+    pub checkpoint_flush_interval_ms: Option<u64>,
+    /// Data files with pending updates that force a `Checkpoint` write before the interval
+    pub checkpoint_flush_max_updates: Option<usize>,
+    /// File to record the filesystem events every Watcher receives to, for `--replay`
+    pub record_events_path: Option<String>,
+    /// Directories below *log_dir* watched by the Watcher of another root, whose data
+    /// files are left to that Watcher. Set by `per_root`, not configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nested_roots: Vec<String>,
+}
+
+/// Configuration for the `TailerManager` and the `Tailer`s it runs.
+///
+/// Optional values fall back to their defaults where they are used.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TailerConfig {
+    /// Seconds running Tailers get to finish on shutdown before they are aborted
+    pub drain_deadline_secs: Option<u64>,
+    /// Maximum number of data files open across all Tailers at once
+    pub max_open_files: Option<usize>,
+    /// Seconds without new data before a Tailer closes its data file until it grows again
+    pub idle_timeout_secs: Option<u64>,
+    /// Failures of a data file's Tailer in a row, panics or errors, before the data file
+    /// is quarantined
+    pub quarantine_after_crashes: Option<u32>,
+    /// Seconds a quarantined data file is skipped before its Tailer is retried
+    pub quarantine_retry_secs: Option<u64>,
+    /// NDJSON file every deliberate loss of data is appended to, see `AuditLog`
+    pub audit_log_path: Option<String>,
+}
+
+/// Configuration for the runtime that assembles and supervises the pipeline.
+///
+/// Optional values fall back to their defaults where they are used.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Milliseconds to wait before the first restart of a failed component
+    pub restart_initial_backoff_ms: Option<u64>,
+    /// Upper bound in milliseconds on the backoff between restarts of a failed component
+    pub restart_max_backoff_ms: Option<u64>,
+    /// Consecutive restarts of a component before the Core Agent gives up and shuts down
+    pub max_restarts: Option<u32>,
+    /// Run on a single worker thread and back off while the host is busy, so the Core
+    /// Agent doesn't compete with the workloads it's observing
+    pub low_priority: Option<bool>,
+    /// Host CPU usage in percent at which low-priority mode starts throttling the pipeline
+    pub busy_cpu_percent: Option<f32>,
+    /// Worker threads of the tokio runtime, defaults to one per CPU core
+    pub worker_threads: Option<usize>,
+    /// Upper bound on the tokio runtime's threads for blocking work, e.g., file I/O
+    pub max_blocking_threads: Option<usize>,
+    /// Lock file shared by Core Agents watching the same data files, only the instance
+    /// holding the lock runs the pipeline while the others stand by
+    pub leader_lock_path: Option<String>,
+}
+
+/// An additional directory for a Watcher to watch. Each root gets its own `Watcher`,
+/// its own include patterns, recursive flag and depth limit, everything else is shared
+/// with the `WatcherConfig` it belongs to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchRoot {
+    pub path: String,
+    pub recursive: Option<bool>,
+    /// How many directory levels below *path* a recursive Watcher descends, see
+    /// `WatcherConfig::max_depth`
+    pub max_depth: Option<usize>,
+    pub include: Option<Vec<String>>,
+}
+
+impl WatcherConfig {
+    /// Split this config into one `WatcherConfig` per watched directory, *log_dir* first
+    /// followed by every entry of *watch_dirs*. Relative paths are resolved against the
+    /// working directory, so data files are tracked under the same paths filesystem
+    /// events report.
+    ///
+    /// Roots may overlap, e.g., `/var/log` and `/var/log/pods`. A data file belongs to the
+    /// most specific root it is under, every other root leaves it alone, see
+    /// `WatcherConfig::in_nested_root`.
+    pub fn per_root(&self) -> Vec<WatcherConfig> {
+        let mut roots = vec![WatcherConfig {
+            log_dir: absolute_path(&self.log_dir),
+            watch_dirs: None,
+            ..self.clone()
+        }];
+
+        for root in self.watch_dirs.iter().flatten() {
+            roots.push(WatcherConfig {
+                log_dir: absolute_path(&root.path),
+                recursive: root.recursive,
+                max_depth: root.max_depth,
+                include: root.include.clone(),
+                watch_dirs: None,
+                ..self.clone()
+            });
+        }
+
+        let log_dirs: Vec<String> = roots.iter().map(|root| root.log_dir.clone()).collect();
+
+        for root in &mut roots {
+            root.nested_roots = log_dirs
+                .iter()
+                .filter(|log_dir| {
+                    **log_dir != root.log_dir && Path::new(log_dir).starts_with(&root.log_dir)
+                })
+                .cloned()
+                .collect();
+        }
+
+        roots
+    }
+
+    /// Whether `path` lies under a directory the Watcher of another, more specific root
+    /// watches, see `per_root`
+    pub fn in_nested_root(&self, path: &Path) -> bool {
+        self.nested_roots
+            .iter()
+            .any(|nested_root| path.starts_with(nested_root))
+    }
+
+    /// Whether *log_dir* is a single data file rather than a directory. A single data
+    /// file is always picked up, regardless of *include* patterns.
+    ///
+    /// A Watcher waits for a *log_dir* that doesn't exist yet to appear before asking.
+    pub fn is_single_file(&self) -> bool {
+        Path::new(&self.log_dir).is_file()
+    }
+
+    /// Deepest level below *log_dir* data files are picked up from, `None` when unlimited.
+    /// A non-recursive Watcher only picks up data files directly in *log_dir*.
+    pub fn depth_limit(&self) -> Option<usize> {
+        if !self.recursive.unwrap_or(true) {
+            return Some(1);
+        }
+
+        self.max_depth
+    }
+
+    /// Whether `path` lies within the configured recursion depth of *log_dir*, so
+    /// filesystem events and discovery agree on which data files are watched
+    pub fn within_depth(&self, path: &Path) -> bool {
+        let Some(limit) = self.depth_limit() else {
+            return true;
+        };
+
+        match path.strip_prefix(&self.log_dir) {
+            Ok(relative) => relative.components().count() <= limit,
+            Err(_) => false,
+        }
+    }
+
+    /// Compiled *include* patterns, `None` when the default file filter applies
+    pub fn include_set(&self) -> Result<Option<RegexSet>> {
+        self.include
+            .as_ref()
+            .map(|patterns| {
+                RegexSet::new(patterns)
+                    .with_context(|| format!("invalid include pattern for {}", self.log_dir))
+            })
+            .transpose()
+    }
+}
+
+/// `path` made absolute against the working directory, or as it is if that fails
+fn absolute_path(path: &str) -> String {
+    std::path::absolute(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Apply a single override to a parsed config. Overrides set keys within a section, and
+/// the section has to still deserialize afterwards, unless it didn't before either, so
+/// unknown keys and values of the wrong type are rejected.
+fn apply_override(value: &mut toml::Value, key_path: &[String], raw_value: &str) -> Result<()> {
+    let Some((section, keys)) = key_path.split_first() else {
+        return Err(anyhow!("empty config key"));
+    };
+
+    if keys.is_empty() {
+        return Err(anyhow!("{section} is a config section, override one of its keys instead"));
+    }
+
+    let before = check_section(value, section).err().map(|e| format!("{e:#}"));
+
+    set_override(value, key_path, raw_value)?;
+
+    match check_section(value, section) {
+        // already broken by an earlier layer, which is reported once the config is loaded
+        Err(e) if before.as_deref() == Some(format!("{e:#}").as_str()) => Ok(()),
+        result => result,
+    }
+}
+
+/// Deserialize a single section of a parsed config on its own, a missing section as an
+/// empty one
+fn check_section(value: &toml::Value, section: &str) -> Result<()> {
+    let section_value = value
+        .get(section)
+        .cloned()
+        .unwrap_or_else(|| toml::Value::Table(toml::Table::new()));
+
+    match section {
+        "watcher" => drop(section_value.try_into::<WatcherConfig>()?),
+        "tailer" => drop(section_value.try_into::<TailerConfig>()?),
+        "runtime" => drop(section_value.try_into::<RuntimeConfig>()?),
+        _ => return Err(anyhow!("unknown config section {section}")),
+    }
+
+    Ok(())
+}
+
+/// Set the value at `key_path` in a parsed config, creating missing tables on the way.
+/// A table is never replaced as a whole.
+fn set_override(value: &mut toml::Value, key_path: &[String], raw_value: &str) -> Result<()> {
+    let Some((last, parents)) = key_path.split_last() else {
+        return Err(anyhow!("empty config key"));
+    };
+
+    let mut table = value
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("config root is not a table"))?;
+
+    for key in parents {
+        table = table
+            .entry(key.as_str())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("config key {key} is not a table"))?;
+    }
+
+    if table.get(last).is_some_and(toml::Value::is_table) {
+        return Err(anyhow!("config key {last} is a table, override one of its keys instead"));
+    }
+
+    table.insert(last.clone(), parse_override_value(raw_value));
+
+    Ok(())
+}
+
+/// Read an override value as a single TOML value, falling back to a plain string. Only
+/// a complete value parses, so a value can't smuggle in further keys, e.g., on a new line.
+fn parse_override_value(raw_value: &str) -> toml::Value {
+    raw_value
+        .parse::<toml::Value>()
+        .unwrap_or_else(|_| toml::Value::String(raw_value.to_string()))
+}
+
+/// Interpolate every string in a parsed config with `env_vars`, see
+/// [`Config::load_layered`]
+fn interpolate(value: &mut toml::Value, env_vars: &[(String, String)]) -> Result<()> {
+    match value {
+        toml::Value::String(s) => {
+            *s = interpolate_string(s, env_vars)?;
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate(value, env_vars)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                interpolate(value, env_vars)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn interpolate_string(s: &str, env_vars: &[(String, String)]) -> Result<String> {
+    let mut missing = None;
+
+    let interpolated = ENV_VAR_PATTERN.replace_all(s, |caps: &Captures| {
+        if !caps[1].is_empty() {
+            return caps[0][1..].to_string();
+        }
+
+        let name = &caps[2];
+        let value = env_vars
+            .iter()
+            .find(|(var, _)| var == name)
+            .map(|(_, value)| value.clone());
+
+        match (value, caps.get(3)) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.as_str().to_string(),
+            (None, None) => {
+                missing.get_or_insert_with(|| name.to_string());
+                String::new()
+            }
+        }
+    });
+
+    if let Some(name) = missing {
+        return Err(anyhow!("environment variable {name} referenced in config is not set"));
+    }
+
+    match interpolated.strip_prefix(FILE_VALUE_PREFIX) {
+        Some(file_path) => {
+            let contents = fs::read_to_string(file_path)
+                .with_context(|| format!("reading config value from {file_path}"))?;
+
+            Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+        }
+        None => Ok(interpolated.into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::ensure;
+    use std::path::PathBuf;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// File of a single test, removed when the test is done
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, contents: &str) -> Result<Self> {
+            let path = std::env::temp_dir().join(format!("ves-{name}-{}", std::process::id()));
+            fs::write(&path, contents)?;
+            Ok(Self(path))
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn variables_are_interpolated_with_their_defaults() -> Result<()> {
+        let vars = env(&[("HOST", "collector"), ("PORT", "4317")]);
+
+        let interpolated = interpolate_string("http://${HOST}:${PORT}/${PREFIX:-v1}", &vars)?;
+        ensure!(
+            interpolated == "http://collector:4317/v1",
+            "expected both variables and the default, got {interpolated}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_variable_without_a_default_is_an_error() -> Result<()> {
+        let result = interpolate_string("${TOKEN}", &env(&[]));
+
+        ensure!(
+            result.as_ref().is_err_and(|e| e.to_string().contains("TOKEN")),
+            "expected the missing TOKEN to be reported, got {result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_variable_is_kept_literally() -> Result<()> {
+        let vars = env(&[("HOST", "collector")]);
+
+        let interpolated = interpolate_string("$${HOST} is ${HOST}, $${MISSING} is fine", &vars)?;
+        ensure!(
+            interpolated == "${HOST} is collector, ${MISSING} is fine",
+            "expected the escaped variables to be kept, got {interpolated}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_value_is_read_after_interpolation() -> Result<()> {
+        let secret = TestFile::new("config-secret", "s3cret\n")?;
+        let dir = secret.0.parent().map(Path::to_path_buf).unwrap_or_default();
+        let name = secret.0.file_name().map(|name| name.to_string_lossy().into_owned());
+
+        let vars = env(&[("SECRETS_DIR", &dir.to_string_lossy())]);
+        let value = format!("file:${{SECRETS_DIR}}/{}", name.unwrap_or_default());
+
+        let interpolated = interpolate_string(&value, &vars)?;
+        ensure!(
+            interpolated == "s3cret",
+            "expected the secret without its trailing newline, got {interpolated}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_value_is_an_error() -> Result<()> {
+        let result = interpolate_string("file:/nonexistent/ves/token", &env(&[]));
+
+        ensure!(result.is_err(), "expected the missing file to be reported, got {result:?}");
+
+        Ok(())
+    }
+
+    const CONFIG: &str = r#"
+        [watcher]
+        log_dir = "/var/log/app"
+
+        [tailer]
+        max_open_files = 100
+    "#;
+
+    fn load(raw: &str, vars: &[(&str, &str)], cli_overrides: &[&str]) -> Result<Config> {
+        let cli_overrides: Vec<String> = cli_overrides.iter().map(|o| o.to_string()).collect();
+        Config::from_layers(Path::new("ves.toml"), raw, &env(vars), &cli_overrides)
+    }
+
+    /// Full chain of `result`'s error, empty if there is none
+    fn error_of<T>(result: &Result<T>) -> String {
+        result
+            .as_ref()
+            .err()
+            .map(|e| format!("{e:#}"))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn later_layers_take_precedence() -> Result<()> {
+        let env_var = [("VES_TAILER__MAX_OPEN_FILES", "200")];
+
+        let file_only = load(CONFIG, &[], &[])?;
+        let with_env = load(CONFIG, &env_var, &[])?;
+        let with_cli = load(CONFIG, &env_var, &["tailer.max_open_files=300"])?;
+
+        ensure!(
+            file_only.tailer.max_open_files == Some(100)
+                && with_env.tailer.max_open_files == Some(200)
+                && with_cli.tailer.max_open_files == Some(300),
+            "expected file < env < --set, got {:?}, {:?} and {:?}",
+            file_only.tailer.max_open_files,
+            with_env.tailer.max_open_files,
+            with_cli.tailer.max_open_files
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overrides_are_read_as_toml_values_or_strings() -> Result<()> {
+        let config = load(
+            CONFIG,
+            &[
+                ("VES_WATCHER__RECURSIVE", "false"),
+                ("VES_RUNTIME__LEADER_LOCK_PATH", "/run/ves.lock"),
+            ],
+            &["watcher.include=['\\.json$']"],
+        )?;
+
+        ensure!(
+            config.watcher.recursive == Some(false)
+                && config.watcher.include == Some(vec![r"\.json$".to_string()])
+                && config.runtime.leader_lock_path.as_deref() == Some("/run/ves.lock"),
+            "expected a boolean, an array and a string, got {config:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_value_cannot_set_other_keys() -> Result<()> {
+        let config = load(
+            CONFIG,
+            &[],
+            &["runtime.leader_lock_path=\"/run/ves.lock\"\nmax_restarts = 1"],
+        )?;
+
+        ensure!(
+            config.runtime.max_restarts.is_none()
+                && config.runtime.leader_lock_path.as_deref()
+                    == Some("\"/run/ves.lock\"\nmax_restarts = 1"),
+            "expected the whole value to be taken as a string, got {:?}",
+            config.runtime
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_key_paths_only_reach_known_keys() -> Result<()> {
+        let unknown_key = load(CONFIG, &[("VES_TAILER__MAX_OPEN_FILE", "1")], &[]);
+        ensure!(
+            error_of(&unknown_key).contains("VES_TAILER__MAX_OPEN_FILE"),
+            "expected the misspelled key to be rejected, got {unknown_key:?}"
+        );
+
+        let too_deep = load(CONFIG, &[], &["runtime.leader.lock_path=/run/ves.lock"]);
+        ensure!(
+            error_of(&too_deep).contains("--set runtime.leader.lock_path"),
+            "expected the nested unknown key to be rejected, got {too_deep:?}"
+        );
+
+        let unknown_section = load(CONFIG, &[("VES_EMBEDDER__URL", "http://localhost")], &[]);
+        ensure!(
+            error_of(&unknown_section).contains("unknown config section embedder"),
+            "expected the unknown section to be rejected, got {unknown_section:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn section_cannot_be_replaced_as_a_whole() -> Result<()> {
+        let result = load(CONFIG, &[("VES_WATCHER", "/var/log/other")], &[]);
+
+        ensure!(
+            error_of(&result).contains("VES_WATCHER"),
+            "expected the section override to be rejected, got {result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_value_is_reported_with_its_layer() -> Result<()> {
+        let from_env = load(CONFIG, &[("VES_TAILER__IDLE_TIMEOUT_SECS", "soon")], &[]);
+        ensure!(
+            error_of(&from_env).contains("VES_TAILER__IDLE_TIMEOUT_SECS"),
+            "expected the environment variable to be blamed, got {from_env:?}"
+        );
+
+        let from_cli = load(CONFIG, &[], &["tailer.idle_timeout_secs=soon"]);
+        ensure!(
+            error_of(&from_cli).contains("--set tailer.idle_timeout_secs"),
+            "expected the --set override to be blamed, got {from_cli:?}"
+        );
+
+        // a bad value in the file isn't blamed on an unrelated override of its section
+        let bad_file = CONFIG.replace("max_open_files = 100", "max_open_files = \"many\"");
+        let from_file = load(&bad_file, &[("VES_TAILER__IDLE_TIMEOUT_SECS", "5")], &[]);
+        let error = error_of(&from_file);
+        ensure!(
+            error.contains("invalid config in ves.toml") && !error.contains("VES_"),
+            "expected the config file to be blamed, got {from_file:?}"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+pub mod cli;
+pub mod config_watch;
+pub mod dry_run;
+pub mod leader;
+pub mod load_config;
+pub mod replay;
+pub mod systemd;
+pub mod throttle;
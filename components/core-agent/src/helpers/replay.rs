@@ -0,0 +1,601 @@
+// Local crates
+use crate::helpers::throttle::Throttle;
+use crate::tailer::{
+    models::{Quarantine, TailerContext, TailerHandle},
+    tailer_events::{handle_event, translate_event},
+};
+use crate::watcher::{
+    discovery::track,
+    models::{
+        EventFilter, FileId, Recorded, RecordedEvent, Watcher, WatcherEvent, WatcherPayload,
+    },
+    store::CheckpointUpdate,
+};
+
+// External crates
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Capacity of the simulated pipeline's channels. They are emptied after every replayed
+/// entry, which produces at most a handful of `WatcherPayload`s and `CheckpointUpdate`s.
+const SIMULATION_CHANNEL_CAPACITY: usize = 1024;
+
+/// Real `Watcher`s and the `TailerManager`'s event handling, driven by an event recording
+/// instead of the filesystem, see `EventRecorder`.
+///
+/// Each `Started` entry starts a Watcher for its root with the recorded config and
+/// `Checkpoint`, and every later entry of that root is handed to it the way the running
+/// Watcher handled it, at the recorded time. What the Watchers send downstream goes
+/// through `handle_event`, as in the `TailerManager`. Tailers are started, renamed and
+/// stopped for real but cancelled before they run, so they never touch the filesystem.
+struct Simulation {
+    watchers: HashMap<String, (Watcher, EventFilter)>,
+    clock: Instant,
+    payloads_tx: mpsc::Sender<WatcherPayload>,
+    payloads_rx: mpsc::Receiver<WatcherPayload>,
+    updates_tx: mpsc::Sender<CheckpointUpdate>,
+    updates_rx: mpsc::Receiver<CheckpointUpdate>,
+    tailers: HashMap<FileId, TailerHandle>,
+    quarantine: Quarantine,
+    context: TailerContext,
+    cancel: CancellationToken,
+}
+
+/// What replaying a single recorded entry did
+#[derive(Debug, Default)]
+struct Step {
+    watcher_events: Vec<WatcherEvent>,
+    checkpoint_updates: Vec<CheckpointUpdate>,
+}
+
+impl Simulation {
+    fn new() -> Self {
+        let (payloads_tx, payloads_rx) = mpsc::channel(SIMULATION_CHANNEL_CAPACITY);
+        let (updates_tx, updates_rx) = mpsc::channel(SIMULATION_CHANNEL_CAPACITY);
+
+        // every Tailer's token is a child of this one, see `Tailer::run`
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let context = TailerContext {
+            output: mpsc::channel(1).0,
+            checkpoint_updates: updates_tx.clone(),
+            fd_budget: Arc::new(Semaphore::new(1)),
+            idle_timeout: Duration::ZERO,
+            throttle: Throttle::default(),
+        };
+
+        Self {
+            watchers: HashMap::new(),
+            clock: Instant::now(),
+            payloads_tx,
+            payloads_rx,
+            updates_tx,
+            updates_rx,
+            tailers: HashMap::new(),
+            // Tailers never run, so they never crash
+            quarantine: Quarantine::new(u32::MAX, Duration::ZERO),
+            context,
+            cancel,
+        }
+    }
+
+    /// Replay a single recorded entry
+    async fn step(&mut self, recorded: Recorded) -> Result<Step> {
+        let now = self.clock + Duration::from_micros(recorded.at_us);
+
+        match recorded.event {
+            RecordedEvent::Started {
+                root,
+                config,
+                single_file,
+                checkpoint,
+            } => {
+                let filter = EventFilter::new(&config, single_file)?;
+                let watcher = Watcher::new(
+                    config,
+                    checkpoint,
+                    self.payloads_tx.clone(),
+                    self.updates_tx.clone(),
+                    None,
+                    None,
+                );
+
+                self.watchers.insert(root, (watcher, filter));
+            }
+
+            RecordedEvent::Filesystem { root, event, paths } => {
+                let (watcher, filter) = self.watcher(&root)?;
+                watcher.on_filesystem_event(event, &paths, now, filter).await?;
+            }
+
+            RecordedEvent::Discovered { root, state, event } => {
+                let (watcher, _) = self.watcher(&root)?;
+                track(
+                    &mut watcher.checkpoint,
+                    &watcher.output,
+                    &watcher.updates,
+                    state,
+                    event,
+                )
+                .await?;
+            }
+
+            RecordedEvent::Pruned { root, removed } => {
+                let (watcher, _) = self.watcher(&root)?;
+
+                for id in removed {
+                    watcher.checkpoint.files.remove(&id);
+                    watcher.updates.send(CheckpointUpdate::Remove(id)).await?;
+                }
+            }
+
+            RecordedEvent::Tick { root, paths } => {
+                let (watcher, filter) = self.watcher(&root)?;
+                watcher.on_tick(&paths, now, filter).await?;
+            }
+        }
+
+        let mut step = Step::default();
+
+        while let Ok(update) = self.updates_rx.try_recv() {
+            step.checkpoint_updates.push(update);
+        }
+
+        while let Ok(payload) = self.payloads_rx.try_recv() {
+            step.watcher_events.push(payload.event.clone());
+
+            for event in translate_event(payload) {
+                handle_event(
+                    event,
+                    &mut self.tailers,
+                    &mut self.quarantine,
+                    &self.context,
+                    &self.cancel,
+                )
+                .await;
+            }
+        }
+
+        Ok(step)
+    }
+
+    fn watcher(&mut self, root: &str) -> Result<(&mut Watcher, &EventFilter)> {
+        self.watchers
+            .get_mut(root)
+            .map(|(watcher, filter)| (watcher, &*filter))
+            .ok_or_else(|| anyhow!("no Watcher was started for {root}"))
+    }
+
+    /// Data files with a Tailer, ordered so they print the same way on every replay
+    fn running(&self) -> Vec<(FileId, PathBuf)> {
+        let mut running: Vec<(FileId, PathBuf)> = self
+            .tailers
+            .iter()
+            .map(|(id, handle)| (*id, handle.path.clone()))
+            .collect();
+
+        running.sort_unstable_by_key(|(id, _)| (id.dev, id.inode));
+        running
+    }
+}
+
+/// Replay an event recording, see `EventRecorder`, through the Watchers' event
+/// translation and the `TailerManager`'s event handling, and print every step: the
+/// recorded entry, the `Checkpoint` updates and Watcher events it led to, and the Tailers
+/// running afterwards.
+///
+/// Nothing touches the filesystem besides reading the recording, so a rotation bug can be
+/// replayed deterministically anywhere.
+pub async fn replay(path: &Path) -> Result<()> {
+    let recording = fs::read_to_string(path)
+        .with_context(|| format!("reading event recording {}", path.display()))?;
+
+    let mut simulation = Simulation::new();
+
+    for (number, line) in recording.lines().enumerate() {
+        let number = number + 1;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let recorded: Recorded = serde_json::from_str(line)
+            .with_context(|| format!("parsing line {number} of {}", path.display()))?;
+
+        println!("#{number} +{}ms {}", recorded.at_us / 1000, describe(&recorded.event));
+
+        let step = simulation.step(recorded).await?;
+
+        for update in &step.checkpoint_updates {
+            println!("   checkpoint {update:?}");
+        }
+
+        for event in &step.watcher_events {
+            println!("   {event:?}");
+        }
+
+        let running = simulation.running();
+        println!("   running: {}", running.len());
+
+        for (id, path) in running {
+            println!("     {}:{} {}", id.dev, id.inode, path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(event: &RecordedEvent) -> String {
+    match event {
+        RecordedEvent::Started {
+            root, checkpoint, ..
+        } => format!("{root}: started, {} tracked", checkpoint.files.len()),
+        RecordedEvent::Filesystem { root, event, .. } => {
+            format!("{root}: {:?} {:?}", event.kind, event.paths)
+        }
+        RecordedEvent::Discovered { root, state, .. } => {
+            format!("{root}: discovered {}", state.path.display())
+        }
+        RecordedEvent::Pruned { root, removed } => format!("{root}: pruned {}", removed.len()),
+        RecordedEvent::Tick { root, .. } => format!("{root}: tick"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+    use crate::helpers::load_config::WatcherConfig;
+    use crate::watcher::models::{Checkpoint, EventPaths, FileState, PathInfo};
+
+    // External crates
+    use anyhow::ensure;
+    use chrono::Utc;
+    use notify::{
+        Event, EventKind,
+        event::{CreateKind, ModifyKind, RenameMode},
+    };
+
+    const ROOT: &str = "/var/log/app";
+
+    const APP_LOG: FileId = FileId { dev: 1, inode: 10 };
+    const NEW_APP_LOG: FileId = FileId { dev: 1, inode: 11 };
+
+    fn path(name: &str) -> PathBuf {
+        Path::new(ROOT).join(name)
+    }
+
+    fn at(ms: u64, event: RecordedEvent) -> Recorded {
+        Recorded {
+            at_us: ms * 1000,
+            event,
+        }
+    }
+
+    fn started() -> RecordedEvent {
+        RecordedEvent::Started {
+            root: ROOT.to_string(),
+            config: WatcherConfig {
+                log_dir: ROOT.to_string(),
+                ..WatcherConfig::default()
+            },
+            single_file: false,
+            checkpoint: Checkpoint::default(),
+        }
+    }
+
+    fn discovered(id: FileId, name: &str) -> RecordedEvent {
+        RecordedEvent::Discovered {
+            root: ROOT.to_string(),
+            state: FileState {
+                path: path(name),
+                dev: id.dev,
+                inode: id.inode,
+                offset: 0,
+                last_seen: Utc::now(),
+                retired: false,
+                skipped: false,
+            },
+            event: WatcherEvent::FileDiscovered {
+                id,
+                path: path(name),
+            },
+        }
+    }
+
+    /// Paths looked up on disk, each existing as the given identity and symlink flag
+    fn event_paths(existing: &[(&str, FileId, bool)]) -> EventPaths {
+        EventPaths {
+            paths: existing
+                .iter()
+                .map(|(name, id, symlink)| PathInfo {
+                    path: path(name),
+                    id: *id,
+                    symlink: *symlink,
+                    dir: false,
+                    len: 0,
+                    excluded: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// A filesystem event on `name`, which exists as `existing` afterwards, if at all
+    fn filesystem(event: Event, existing: &[(&str, FileId, bool)]) -> RecordedEvent {
+        RecordedEvent::Filesystem {
+            root: ROOT.to_string(),
+            event,
+            paths: event_paths(existing),
+        }
+    }
+
+    fn rename(mode: RenameMode, name: &str, cookie: usize) -> Event {
+        Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+            .add_path(path(name))
+            .set_tracker(cookie)
+    }
+
+    fn create(name: &str) -> Event {
+        Event::new(EventKind::Create(CreateKind::File)).add_path(path(name))
+    }
+
+    fn tick() -> RecordedEvent {
+        tick_seeing(&[])
+    }
+
+    /// A tick, with the pending "from" paths existing as `existing`
+    fn tick_seeing(existing: &[(&str, FileId, bool)]) -> RecordedEvent {
+        RecordedEvent::Tick {
+            root: ROOT.to_string(),
+            paths: event_paths(existing),
+        }
+    }
+
+    /// Replay `entries` as recorded lines, so the recording format is covered too, and
+    /// return the last step
+    async fn replay(simulation: &mut Simulation, entries: Vec<Recorded>) -> Result<Step> {
+        let mut last = Step::default();
+
+        for recorded in entries {
+            let line = serde_json::to_string(&recorded)?;
+            last = simulation.step(serde_json::from_str(&line)?).await?;
+        }
+
+        Ok(last)
+    }
+
+    #[tokio::test]
+    async fn logrotate_rename_and_create_keeps_both_tailers() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(0, discovered(APP_LOG, "app.log")),
+                at(100, filesystem(rename(RenameMode::From, "app.log", 1), &[])),
+                at(
+                    100,
+                    filesystem(
+                        rename(RenameMode::To, "app.log.1", 1),
+                        &[("app.log.1", APP_LOG, false)],
+                    ),
+                ),
+                at(110, filesystem(create("app.log"), &[("app.log", NEW_APP_LOG, false)])),
+            ],
+        )
+        .await?;
+
+        let running = simulation.running();
+        ensure!(
+            running == vec![(APP_LOG, path("app.log.1")), (NEW_APP_LOG, path("app.log"))],
+            "expected the rotated app.log.1 to keep its Tailer next to the new app.log, got {running:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_is_renamed_in_the_checkpoint_not_upserted() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        let step = replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(0, discovered(APP_LOG, "app.log")),
+                at(100, filesystem(rename(RenameMode::From, "app.log", 1), &[])),
+                at(
+                    100,
+                    filesystem(
+                        rename(RenameMode::To, "app.log.1", 1),
+                        &[("app.log.1", APP_LOG, false)],
+                    ),
+                ),
+            ],
+        )
+        .await?;
+
+        ensure!(
+            matches!(
+                step.checkpoint_updates.as_slice(),
+                [CheckpointUpdate::Rename { id, path: renamed }] if *id == APP_LOG && *renamed == path("app.log.1")
+            ),
+            "expected only a rename of app.log, got {:?}",
+            step.checkpoint_updates
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn data_file_moved_out_is_stopped_once_the_rename_expires() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        let step = replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(0, discovered(APP_LOG, "app.log")),
+                at(1000, filesystem(rename(RenameMode::From, "app.log", 1), &[])),
+                at(1500, tick()),
+            ],
+        )
+        .await?;
+
+        ensure!(
+            step.watcher_events.is_empty() && simulation.running().len() == 1,
+            "expected app.log to be kept while its rename may still be paired, got {:?}",
+            step.watcher_events
+        );
+
+        let step = replay(&mut simulation, vec![at(2500, tick())]).await?;
+
+        ensure!(
+            matches!(
+                step.watcher_events.as_slice(),
+                [WatcherEvent::FileRemoved { id, .. }] if *id == APP_LOG
+            ),
+            "expected app.log to be removed, got {:?}",
+            step.watcher_events
+        );
+        ensure!(
+            simulation.running().is_empty(),
+            "expected the Tailer of app.log to be stopped, got {:?}",
+            simulation.running()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn data_file_moved_out_and_back_is_kept() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        // the move back in was missed, but app.log is at its path again by the tick
+        let step = replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(0, discovered(APP_LOG, "app.log")),
+                at(1000, filesystem(rename(RenameMode::From, "app.log", 1), &[])),
+                at(2500, tick_seeing(&[("app.log", APP_LOG, false)])),
+            ],
+        )
+        .await?;
+
+        ensure!(
+            step.watcher_events.is_empty(),
+            "expected app.log not to be removed, got {:?}",
+            step.watcher_events
+        );
+
+        let running = simulation.running();
+        ensure!(
+            running == vec![(APP_LOG, path("app.log"))],
+            "expected app.log to keep its Tailer, got {running:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retargeted_symlink_drains_its_previous_target_next_to_the_new_one() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(0, discovered(APP_LOG, "app.log")),
+                at(100, filesystem(create("app.log"), &[("app.log", NEW_APP_LOG, true)])),
+            ],
+        )
+        .await?;
+
+        let running = simulation.running();
+        // the previous target's Tailer is left to drain it rather than cancelled
+        ensure!(
+            running == vec![(APP_LOG, path("app.log")), (NEW_APP_LOG, path("app.log"))],
+            "expected the symlink's previous target to drain next to its new one, got {running:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn excluded_data_file_moved_in_is_tracked_at_its_end_without_a_tailer() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        let step = replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(
+                    100,
+                    RecordedEvent::Filesystem {
+                        root: ROOT.to_string(),
+                        event: rename(RenameMode::To, "old.log", 1),
+                        paths: EventPaths {
+                            paths: vec![PathInfo {
+                                path: path("old.log"),
+                                id: APP_LOG,
+                                symlink: false,
+                                dir: false,
+                                len: 4096,
+                                excluded: true,
+                            }],
+                        },
+                    },
+                ),
+            ],
+        )
+        .await?;
+
+        ensure!(
+            matches!(
+                step.checkpoint_updates.as_slice(),
+                [CheckpointUpdate::Upsert(state)] if state.id() == APP_LOG && state.skipped && state.offset == 4096
+            ),
+            "expected old.log to be tracked as skipped at its end, got {:?}",
+            step.checkpoint_updates
+        );
+        ensure!(
+            step.watcher_events.is_empty() && simulation.running().is_empty(),
+            "expected old.log to get no Tailer, got {:?}",
+            step.watcher_events
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files_not_matching_the_include_filter_get_no_tailer() -> Result<()> {
+        let mut simulation = Simulation::new();
+
+        let step = replay(
+            &mut simulation,
+            vec![
+                at(0, started()),
+                at(100, filesystem(create("notes.md"), &[("notes.md", APP_LOG, false)])),
+            ],
+        )
+        .await?;
+
+        ensure!(
+            step.watcher_events.is_empty() && simulation.running().is_empty(),
+            "expected notes.md to be ignored, got {:?}",
+            step.watcher_events
+        );
+
+        Ok(())
+    }
+}
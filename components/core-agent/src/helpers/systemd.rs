@@ -0,0 +1,71 @@
+// External crates
+use anyhow::Result;
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use {
+    anyhow::Context,
+    std::os::linux::net::SocketAddrExt,
+    std::os::unix::ffi::OsStrExt,
+    std::os::unix::net::{SocketAddr, UnixDatagram},
+};
+
+/// The Core Agent finished starting up
+pub const READY: &str = "READY=1";
+
+/// The Core Agent is still alive, sent at least once per watchdog interval
+pub const WATCHDOG: &str = "WATCHDOG=1";
+
+/// The Core Agent started shutting down
+pub const STOPPING: &str = "STOPPING=1";
+
+/// Send a state notification to systemd through `$NOTIFY_SOCKET`, see
+/// [sd_notify(3)](https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html).
+/// This is a no-op when the Core Agent isn't running as a `Type=notify` systemd service.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) -> Result<()> {
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    // a leading '@' means the socket lives in the abstract namespace
+    let addr = match socket_path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&socket_path)?,
+    };
+
+    socket
+        .send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("sending {state} to systemd"))?;
+
+    Ok(())
+}
+
+/// How often watchdog notifications have to be sent, when systemd's watchdog is enabled
+/// for this process. Notifications are due at half of `WatchdogSec=`, as recommended by
+/// [sd_watchdog_enabled(3)](https://www.freedesktop.org/software/systemd/man/latest/sd_watchdog_enabled.html).
+#[cfg(target_os = "linux")]
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// systemd only runs on Linux, elsewhere there is nothing to notify
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) -> Result<()> {
+    Ok(())
+}
+
+/// systemd only runs on Linux, elsewhere there is no watchdog
+#[cfg(not(target_os = "linux"))]
+pub fn watchdog_interval() -> Option<Duration> {
+    None
+}
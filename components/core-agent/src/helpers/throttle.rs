@@ -0,0 +1,66 @@
+// External crates
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use sysinfo::System;
+use tokio::time::{Duration, MissedTickBehavior, interval, sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How often the host's CPU usage is sampled in low-priority mode
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a throttled pipeline stage backs off before doing its next unit of work
+const THROTTLE_PAUSE: Duration = Duration::from_millis(100);
+
+/// Percentage points below the busy threshold the host has to drop to before the
+/// throttle is released, so it doesn't flap around the threshold
+const THROTTLE_HYSTERESIS_PERCENT: f32 = 10.0;
+
+/// Shared flag telling pipeline stages to back off while the host is busy. The default
+/// `Throttle` is never engaged, it is only driven by [`monitor_host_cpu`] in low-priority
+/// mode.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle(Arc<AtomicBool>);
+
+impl Throttle {
+    /// Whether the host is currently busy and pipeline stages should back off
+    pub fn is_engaged(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Back off for a moment if the host is busy, called by pipeline stages between
+    /// units of work, e.g., by a Tailer between chunks
+    pub async fn pause_if_engaged(&self) {
+        if self.is_engaged() {
+            sleep(THROTTLE_PAUSE).await;
+        }
+    }
+}
+
+/// Sample the host's CPU usage until `cancel` is cancelled, engaging `throttle` while
+/// usage is at or above `busy_percent` so the Core Agent yields the CPU to the
+/// workloads it's observing.
+pub async fn monitor_host_cpu(throttle: Throttle, busy_percent: f32, cancel: CancellationToken) {
+    let mut system = System::new();
+    let mut ticker = interval(CPU_SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+
+        system.refresh_cpu_usage();
+        let usage = system.global_cpu_usage();
+        let engaged = throttle.is_engaged();
+
+        if !engaged && usage >= busy_percent {
+            info!(usage, busy_percent, "Host is busy, throttling the pipeline");
+            throttle.0.store(true, Ordering::Relaxed);
+        } else if engaged && usage < busy_percent - THROTTLE_HYSTERESIS_PERCENT {
+            info!(usage, busy_percent, "Host is no longer busy, releasing the pipeline throttle");
+            throttle.0.store(false, Ordering::Relaxed);
+        }
+    }
+}
@@ -1,6 +1,37 @@
+mod error;
+mod helpers;
+mod runtime;
 mod tailer;
 mod watcher;
 
-fn main() {
+// Local crates
+use crate::error::VesError;
+use crate::helpers::{cli::Cli, dry_run, load_config::Config, replay::replay};
+
+// External crates
+use anyhow::Result;
+use clap::Parser;
+
+fn main() -> Result<()> {
     // Main entrypoint simply delegates control to CLI layer.
+    let cli = Cli::parse();
+    tracing_subscriber::fmt().init();
+
+    if let Some(recording) = &cli.replay {
+        // replaying is deterministic, it never needs more than a single thread
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(replay(recording));
+    }
+
+    let config = Config::load_layered(&cli.config, &cli.overrides).map_err(VesError::Config)?;
+
+    if cli.dry_run {
+        return dry_run::run(&config);
+    }
+
+    runtime::build(&config.runtime)
+        .map_err(VesError::Runtime)?
+        .block_on(runtime::run(config, cli))
 }
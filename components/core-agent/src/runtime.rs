@@ -0,0 +1,620 @@
+// Local crates
+use crate::{
+    error::VesError,
+    helpers::{
+        cli::Cli,
+        config_watch::watch_config,
+        leader::LeaderLock,
+        load_config::{Config, RuntimeConfig},
+        systemd,
+        throttle::{Throttle, monitor_host_cpu},
+    },
+    tailer::models::{TailerManager, TailerPayload},
+    watcher::{
+        models::{EventRecorder, Watcher, WatcherPayload},
+        store::{CheckpointUpdate, CheckpointWriter},
+        watcher::split_by_root,
+    },
+};
+
+// External crates
+use anyhow::{Result, anyhow};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{broadcast, mpsc},
+    time::{Duration, Instant, interval, sleep},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Capacity of the channel carrying `WatcherPayload`s from the Watchers to the `TailerManager`
+const WATCHER_CHANNEL_CAPACITY: usize = 1024;
+
+/// Capacity of the channel carrying `CheckpointUpdate`s to the `CheckpointWriter`
+const CHECKPOINT_UPDATES_CAPACITY: usize = 4096;
+
+/// How often the runtime checks that its critical components are still running
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default wait before the first restart of a failed component
+const DEFAULT_RESTART_INITIAL_BACKOFF_MS: u64 = 500;
+
+/// Default upper bound on the wait between restarts of a failed component
+const DEFAULT_RESTART_MAX_BACKOFF_MS: u64 = 60_000;
+
+/// Default consecutive restarts of a component before the Core Agent gives up
+const DEFAULT_MAX_RESTARTS: u32 = 10;
+
+/// A component that ran at least this long is considered recovered, and its
+/// restart count and backoff start over
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(300);
+
+/// How often a standby Core Agent tries to take over the leader lock
+const LEADER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default host CPU usage in percent at which low-priority mode throttles the pipeline
+const DEFAULT_BUSY_CPU_PERCENT: f32 = 80.0;
+
+/// Worker threads of the tokio runtime in low-priority mode
+const LOW_PRIORITY_WORKER_THREADS: usize = 1;
+
+/// Blocking threads of the tokio runtime in low-priority mode, bounding concurrent
+/// filesystem operations
+const LOW_PRIORITY_MAX_BLOCKING_THREADS: usize = 4;
+
+/// Build the tokio runtime the Core Agent runs on. In low-priority mode the runtime is
+/// kept to a single worker thread and a handful of blocking threads, explicitly
+/// configured thread counts take precedence over both tokio's and low-priority defaults.
+pub fn build(config: &RuntimeConfig) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+
+    let low_priority = config.low_priority.unwrap_or(false);
+
+    let worker_threads = config
+        .worker_threads
+        .or(low_priority.then_some(LOW_PRIORITY_WORKER_THREADS));
+    let max_blocking_threads = config
+        .max_blocking_threads
+        .or(low_priority.then_some(LOW_PRIORITY_MAX_BLOCKING_THREADS));
+
+    if let Some(worker_threads) = worker_threads {
+        if worker_threads == 0 {
+            return Err(anyhow!("runtime.worker_threads must be at least 1"));
+        }
+
+        builder.worker_threads(worker_threads);
+    }
+
+    if let Some(max_blocking_threads) = max_blocking_threads {
+        if max_blocking_threads == 0 {
+            return Err(anyhow!("runtime.max_blocking_threads must be at least 1"));
+        }
+
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Assemble the Core Agent's pipeline from `config` and run it until SIGTERM/SIGINT.
+///
+/// ```text
+/// CheckpointStore -> Watcher(s) -> TailerManager -> Tailer(s)
+///                          \              |              /
+///                           `-------> CheckpointWriter <'
+/// ```
+///
+/// Watchers are supervised and restarted with backoff when they fail, see [`supervise`].
+/// The `TailerManager` and `CheckpointWriter` own the receiving ends of the pipeline's
+/// channels and can't be restarted in place, if either of them stops the Core Agent
+/// shuts down with an error so its service manager can restart it.
+///
+/// On shutdown the Watchers stop first, then the `TailerManager` drains its Tailers, and
+/// the `CheckpointWriter` is stopped last so it persists the Tailers' final offsets.
+///
+/// With a leader lock configured, the pipeline is only assembled once this instance holds
+/// the lock, so the checkpoint is loaded after the previous leader let go of it.
+pub async fn run(config: Config, cli: Cli) -> Result<()> {
+    let _leader = match &config.runtime.leader_lock_path {
+        Some(path) => match wait_for_leadership(Path::new(path)).await? {
+            Some(lock) => Some(lock),
+            None => return Ok(()),
+        },
+        None => None,
+    };
+
+    let cancel = CancellationToken::new();
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+    let supervisor_shutdown = CancellationToken::new();
+
+    let writer_cancel = CancellationToken::new();
+    let (updates_tx, updates_rx) = mpsc::channel::<CheckpointUpdate>(CHECKPOINT_UPDATES_CAPACITY);
+    let checkpoint_writer =
+        CheckpointWriter::from_config(&config.watcher, updates_rx, writer_cancel.clone());
+    let checkpoint = checkpoint_writer.load().map_err(VesError::Checkpoint)?;
+
+    let (watcher_tx, watcher_rx) = mpsc::channel::<WatcherPayload>(WATCHER_CHANNEL_CAPACITY);
+
+    let config_watch = tokio::spawn({
+        let running = config.clone();
+        let cancel = supervisor_shutdown.clone();
+
+        async move {
+            if let Err(e) = watch_config(cli.config, cli.overrides, running, cancel).await {
+                warn!(error = %e, "Not watching the config file for changes");
+            }
+        }
+    });
+
+    let throttle = Throttle::default();
+
+    if config.runtime.low_priority.unwrap_or(false) {
+        tokio::spawn(monitor_host_cpu(
+            throttle.clone(),
+            config
+                .runtime
+                .busy_cpu_percent
+                .unwrap_or(DEFAULT_BUSY_CPU_PERCENT),
+            supervisor_shutdown.clone(),
+        ));
+    }
+
+    let mut tailer_manager = TailerManager::new(
+        watcher_rx,
+        shutdown_tx.subscribe(),
+        checkpoint.clone(),
+        cancel.clone(),
+        updates_tx.clone(),
+        throttle,
+        &config.tailer,
+    );
+
+    if let Some(payloads) = tailer_manager.take_output() {
+        tokio::spawn(discard_payloads(payloads));
+    }
+
+    // shared by every Watcher, recording what they receive for `--replay`
+    let recorder = match &config.watcher.record_events_path {
+        Some(path) => match EventRecorder::open(PathBuf::from(path)) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                warn!(error = %e, "Not recording Watcher events");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let snapshots = checkpoint_writer.snapshots();
+    let writer = tokio::spawn(checkpoint_writer.run());
+    let manager = tokio::spawn(tailer_manager.run());
+
+    let mut watchers = Vec::new();
+
+    for (index, (root, root_checkpoint)) in split_by_root(&config.watcher, checkpoint)?
+        .into_iter()
+        .enumerate()
+    {
+        let name = format!("watcher({})", root.log_dir);
+        let watcher_config = config.watcher.clone();
+        let snapshots = snapshots.clone();
+        let recorder = recorder.clone();
+        let output = watcher_tx.clone();
+        let updates = updates_tx.clone();
+        let shutdown_tx = shutdown_tx.clone();
+        let cancel = cancel.clone();
+
+        let mut initial_checkpoint = Some(root_checkpoint);
+
+        watchers.push(tokio::spawn(supervise(
+            name,
+            config.runtime.clone(),
+            supervisor_shutdown.clone(),
+            move || {
+                let initial_checkpoint = initial_checkpoint.take();
+                let watcher_config = watcher_config.clone();
+                let snapshots = snapshots.clone();
+                let root = root.clone();
+                let recorder = recorder.clone();
+                let output = output.clone();
+                let updates = updates.clone();
+
+                let shutdown_rx = shutdown_tx.subscribe();
+                let cancel = cancel.child_token();
+
+                async move {
+                    // a restarted Watcher continues from the CheckpointWriter's Checkpoint,
+                    // including updates that aren't persisted yet, so data files it already
+                    // tracks keep their offsets and aren't discovered again
+                    let root_checkpoint = match initial_checkpoint {
+                        Some(root_checkpoint) => root_checkpoint,
+                        None => {
+                            let checkpoint = snapshots.take().await.map_err(VesError::Checkpoint)?;
+
+                            split_by_root(&watcher_config, checkpoint)
+                                .map_err(VesError::Watch)?
+                                .into_iter()
+                                .nth(index)
+                                .map(|(_, root_checkpoint)| root_checkpoint)
+                                .unwrap_or_default()
+                        }
+                    };
+
+                    let watcher = Watcher::new(
+                        root,
+                        root_checkpoint,
+                        output,
+                        updates,
+                        recorder,
+                        Some(snapshots),
+                    );
+
+                    watcher.run(shutdown_rx, cancel).await.map_err(VesError::Watch)
+                }
+            },
+        )));
+    }
+
+    drop(watcher_tx);
+    drop(updates_tx);
+
+    info!(watchers = watchers.len(), "Core Agent started");
+    notify_systemd(systemd::READY);
+
+    let outcome = wait_for_shutdown(|| {
+        !manager.is_finished()
+            && !writer.is_finished()
+            && watchers.iter().all(|watcher| !watcher.is_finished())
+    })
+    .await;
+
+    info!("Core Agent shutting down");
+    notify_systemd(systemd::STOPPING);
+
+    supervisor_shutdown.cancel();
+    let _ = shutdown_tx.send(());
+
+    for watcher in watchers {
+        log_exit("Watcher supervisor", watcher.await);
+    }
+
+    log_exit("TailerManager", manager.await);
+    let _ = config_watch.await;
+
+    writer_cancel.cancel();
+    log_exit("CheckpointWriter", writer.await);
+
+    info!("Core Agent stopped");
+
+    outcome
+}
+
+/// Run the component produced by `start` until `shutdown` is cancelled. Whenever the
+/// component fails, panics, or stops on its own before shutdown it is started again
+/// after an exponential backoff, configured in `RuntimeConfig`. A component that fails
+/// more than *max_restarts* times in a row makes the supervisor give up with an error.
+///
+/// Each run of the component is spawned as its own task, so a panic is contained to
+/// that run instead of taking down the supervisor.
+pub async fn supervise<F, Fut>(
+    name: String,
+    config: RuntimeConfig,
+    shutdown: CancellationToken,
+    mut start: F,
+) -> Result<()>
+where
+    F: FnMut() -> Fut + Send,
+    Fut: Future<Output = Result<(), VesError>> + Send + 'static,
+{
+    let initial_backoff = Duration::from_millis(
+        config
+            .restart_initial_backoff_ms
+            .unwrap_or(DEFAULT_RESTART_INITIAL_BACKOFF_MS),
+    );
+    let max_backoff = Duration::from_millis(
+        config
+            .restart_max_backoff_ms
+            .unwrap_or(DEFAULT_RESTART_MAX_BACKOFF_MS),
+    );
+    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS);
+
+    let mut backoff = initial_backoff;
+    let mut restarts: u32 = 0;
+
+    loop {
+        let started = Instant::now();
+        let result = tokio::spawn(start()).await;
+
+        if shutdown.is_cancelled() {
+            return Ok(());
+        }
+
+        match result {
+            Ok(Ok(())) => warn!(component = %name, "Component stopped unexpectedly"),
+            Ok(Err(e)) => {
+                error!(component = %name, category = e.category(), error = %e, "Component failed")
+            }
+            Err(e) => error!(component = %name, error = %e, "Component panicked"),
+        }
+
+        if started.elapsed() >= RESTART_RESET_AFTER {
+            backoff = initial_backoff;
+            restarts = 0;
+        }
+
+        restarts += 1;
+
+        if restarts > max_restarts {
+            return Err(anyhow!("{name} failed {max_restarts} times in a row, giving up"));
+        }
+
+        warn!(
+            component = %name,
+            restarts,
+            backoff_ms = backoff.as_millis() as u64,
+            "Restarting component"
+        );
+
+        tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            _ = sleep(backoff) => {}
+        }
+
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+/// Stand by until this instance becomes the leader. Returns `None` if SIGTERM or SIGINT
+/// arrives first. A standby instance already reports itself ready to systemd and keeps
+/// its watchdog fed, it is healthy, just not leading.
+async fn wait_for_leadership(path: &Path) -> Result<Option<LeaderLock>> {
+    if let Some(lock) = LeaderLock::try_acquire(path)? {
+        info!(path = %path.display(), "Acquired leader lock");
+        return Ok(Some(lock));
+    }
+
+    info!(path = %path.display(), "Another Core Agent holds the leader lock, standing by");
+    notify_systemd(systemd::READY);
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut poll = interval(LEADER_POLL_INTERVAL);
+    let mut watchdog = systemd::watchdog_interval().map(interval);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => return Ok(None),
+            _ = sigint.recv() => return Ok(None),
+
+            _ = poll.tick() => {
+                if let Some(lock) = LeaderLock::try_acquire(path)? {
+                    info!(path = %path.display(), "Acquired leader lock, taking over");
+                    return Ok(Some(lock));
+                }
+            }
+
+            _ = async {
+                match watchdog.as_mut() {
+                    Some(watchdog) => {
+                        watchdog.tick().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            } => {
+                notify_systemd(systemd::WATCHDOG);
+            }
+        }
+    }
+}
+
+/// Wait for SIGTERM or SIGINT. Returns an error instead if `healthy` stops holding, i.e.,
+/// a critical component of the pipeline stopped. While waiting, systemd's watchdog is
+/// notified, so systemd also restarts a Core Agent whose runtime loop has wedged.
+async fn wait_for_shutdown(healthy: impl Fn() -> bool) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut health_check = interval(HEALTH_CHECK_INTERVAL);
+    let mut watchdog = systemd::watchdog_interval().map(interval);
+
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => return Ok(()),
+            _ = sigint.recv() => return Ok(()),
+
+            _ = health_check.tick() => {
+                if !healthy() {
+                    return Err(anyhow!("a critical pipeline component stopped unexpectedly"));
+                }
+            }
+
+            _ = async {
+                match watchdog.as_mut() {
+                    Some(watchdog) => {
+                        watchdog.tick().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            } => {
+                notify_systemd(systemd::WATCHDOG);
+            }
+        }
+    }
+}
+
+/// Stand-in for the stage after the Tailers, which doesn't exist yet. Payloads are
+/// dropped as they arrive, so the Tailers never wait on it. It returns once every Tailer
+/// has stopped and the channel is closed.
+async fn discard_payloads(mut payloads: mpsc::Receiver<TailerPayload>) {
+    while payloads.recv().await.is_some() {}
+}
+
+fn log_exit(component: &str, result: Result<Result<()>, tokio::task::JoinError>) {
+    match result {
+        Ok(Err(e)) => error!(component, error = %e, "Exited with an error"),
+        Err(e) => error!(component, error = %e, "Task failed"),
+        Ok(Ok(())) => {}
+    }
+}
+
+fn notify_systemd(state: &str) {
+    if let Err(e) = systemd::notify(state) {
+        warn!(state, error = %e, "Failed to notify systemd");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::ensure;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    fn restart_config(
+        initial_backoff_ms: u64,
+        max_backoff_ms: u64,
+        max_restarts: u32,
+    ) -> RuntimeConfig {
+        RuntimeConfig {
+            restart_initial_backoff_ms: Some(initial_backoff_ms),
+            restart_max_backoff_ms: Some(max_backoff_ms),
+            max_restarts: Some(max_restarts),
+            ..RuntimeConfig::default()
+        }
+    }
+
+    /// Supervise a component that fails after running for `run_for(n)` on its `n`th start,
+    /// and return the supervisor's result along with when each start happened
+    async fn supervise_failing(
+        config: RuntimeConfig,
+        shutdown: CancellationToken,
+        run_for: fn(usize) -> Duration,
+    ) -> (Result<()>, Vec<Instant>) {
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = starts.clone();
+
+        let result = supervise("test".to_string(), config, shutdown, move || {
+            let starts = recorded.clone();
+
+            async move {
+                let run = {
+                    let mut starts = starts.lock().unwrap_or_else(PoisonError::into_inner);
+                    starts.push(Instant::now());
+                    run_for(starts.len())
+                };
+
+                sleep(run).await;
+                Err(VesError::Runtime(anyhow!("component failed")))
+            }
+        })
+        .await;
+
+        let starts = starts.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        (result, starts)
+    }
+
+    /// Whether the time between consecutive starts matches `expected`, give or take the
+    /// timer's millisecond resolution
+    fn waited(starts: &[Instant], expected: &[Duration]) -> bool {
+        let gaps: Vec<Duration> = starts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+        gaps.len() == expected.len()
+            && gaps.iter().zip(expected).all(|(gap, expected)| {
+                *gap >= *expected && *gap - *expected < Duration::from_millis(5)
+            })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_up_to_its_maximum() -> Result<()> {
+        let (_, starts) = supervise_failing(
+            restart_config(100, 300, 4),
+            CancellationToken::new(),
+            |_| Duration::ZERO,
+        )
+        .await;
+
+        let expected = [100, 200, 300, 300].map(Duration::from_millis);
+        ensure!(
+            waited(&starts, &expected),
+            "expected restarts after {expected:?}, got starts at {starts:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn supervisor_gives_up_after_max_restarts() -> Result<()> {
+        let (result, starts) = supervise_failing(
+            restart_config(100, 1000, 3),
+            CancellationToken::new(),
+            |_| Duration::ZERO,
+        )
+        .await;
+
+        ensure!(
+            starts.len() == 4,
+            "expected the first run and 3 restarts, got {} runs",
+            starts.len()
+        );
+        ensure!(
+            result.as_ref().is_err_and(|e| e.to_string().contains("failed 3 times in a row")),
+            "expected the supervisor to give up, got {result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_reset_after_a_long_run() -> Result<()> {
+        // the second run lasts long enough to count as recovered, so its failure is the
+        // first in a row again and restarts after the initial backoff
+        let (result, starts) = supervise_failing(
+            restart_config(100, 1000, 1),
+            CancellationToken::new(),
+            |start| if start == 2 { RESTART_RESET_AFTER } else { Duration::ZERO },
+        )
+        .await;
+
+        let expected = [
+            Duration::from_millis(100),
+            RESTART_RESET_AFTER + Duration::from_millis(100),
+        ];
+        ensure!(
+            waited(&starts, &expected),
+            "expected restarts after {expected:?}, got starts at {starts:?}"
+        );
+        ensure!(
+            result.is_err(),
+            "expected the third failure in a row to be one too many, got {result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_during_backoff_stops_the_supervisor() -> Result<()> {
+        let shutdown = CancellationToken::new();
+
+        let cancel = shutdown.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            cancel.cancel();
+        });
+
+        let (result, starts) =
+            supervise_failing(restart_config(10_000, 10_000, 3), shutdown, |_| Duration::ZERO)
+                .await;
+
+        ensure!(
+            result.is_ok() && starts.len() == 1,
+            "expected the supervisor to stop without restarting, got {result:?} after {} runs",
+            starts.len()
+        );
+
+        Ok(())
+    }
+}
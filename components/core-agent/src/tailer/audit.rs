@@ -0,0 +1,61 @@
+// Local crates
+use crate::tailer::models::{AuditEvent, AuditLog, AuditRecord};
+
+// External crates
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+impl AuditLog {
+    /// Open the audit log at `path`, appending to it if it already exists
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening audit log {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Append `event` to the audit log. The write and its sync run on the blocking pool,
+    /// so they don't stall the `TailerManager`'s worker. A failed write is only logged,
+    /// losing data is never held up by recording it.
+    pub async fn record(&self, event: AuditEvent) {
+        let record = AuditRecord {
+            at: Utc::now(),
+            event,
+        };
+
+        let file = self.file.clone();
+        let written = match serde_json::to_vec(&record) {
+            Ok(line) => tokio::task::spawn_blocking(move || write(&file, line))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|written| written),
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = written {
+            warn!(path = %self.path.display(), error = %e, ?record, "Failed to write to the audit log");
+        }
+    }
+}
+
+/// Append a serialized `AuditRecord` as a line to the audit log `file` and sync it
+fn write(file: &Mutex<fs::File>, mut line: Vec<u8>) -> Result<()> {
+    line.push(b'\n');
+
+    let mut file = file.lock().map_err(|_| anyhow!("audit log lock poisoned"))?;
+    file.write_all(&line)?;
+    file.sync_data()?;
+
+    Ok(())
+}
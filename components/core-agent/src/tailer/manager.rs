@@ -1,33 +1,93 @@
 // Local crates
+use crate::error::VesError;
+use crate::helpers::{load_config::TailerConfig, throttle::Throttle};
 use crate::tailer::{
     models::{
+        AuditEvent,
+        AuditLog,
+        AuditedFile,
+        Quarantine,
+        TailerContext,
+        TailerHandle,
         TailerManager,
         TailerPayload,
     },
+    quarantine::panic_message,
+    tailer::start_tailer,
     tailer_events::{handle_event, translate_event},
 };
-use crate::watcher::models::{Checkpoint, WatcherPayload};
+use crate::watcher::{
+    models::{Checkpoint, FileId, WatcherPayload},
+    store::CheckpointUpdate,
+};
 
 // External crates
 use anyhow::Result;
 use std::collections::HashMap;
-use tokio::sync::{mpsc, broadcast};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::{Semaphore, mpsc, broadcast};
+use tokio::time::{Duration, Instant, MissedTickBehavior, interval, timeout_at};
 use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Default time running Tailers get to finish on shutdown
+const DEFAULT_DRAIN_DEADLINE_SECS: u64 = 30;
+
+/// Default maximum number of data files open across all Tailers
+const DEFAULT_MAX_OPEN_FILES: usize = 1024;
+
+/// Default time without new data before a Tailer closes its data file
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+
+/// Default number of failures of a data file's Tailer in a row before the data file is
+/// quarantined
+const DEFAULT_QUARANTINE_AFTER_CRASHES: u32 = 3;
+
+/// Default time a quarantined data file is skipped before its Tailer is retried
+const DEFAULT_QUARANTINE_RETRY_SECS: u64 = 300;
+
+/// Capacity of the channel carrying `TailerPayload`s from all Tailers to the next stage
+const TAILER_OUTPUT_CAPACITY: usize = 1024;
+
+/// How often finished Tailers are collected and failed Tailers are restarted
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
 
 impl TailerManager {
     /// Create a new `TailerManager` once when the pipeline starts for the first
     /// time or restarts
     pub fn new(
-        watcher_rx: broadcast::Receiver<WatcherPayload>,
+        watcher_rx: mpsc::Receiver<WatcherPayload>,
         shutdown_rx: broadcast::Receiver<()>,
         checkpoint: Checkpoint,
         parent_cancel: CancellationToken,
+        checkpoint_updates: mpsc::Sender<CheckpointUpdate>,
+        throttle: Throttle,
+        config: &TailerConfig,
     ) -> Self {
         let cancel = parent_cancel.child_token();
 
-        // [TODO]: Use output_rx in the next stage of the normalization stage
-        // of the pipeline to receive TailerPayloads
-        let (output_tx, output_rx) = mpsc::channel::<TailerPayload>(1024);
+        let (output_tx, output_rx) = mpsc::channel::<TailerPayload>(TAILER_OUTPUT_CAPACITY);
+
+        let context = TailerContext {
+            output: output_tx,
+            checkpoint_updates,
+            fd_budget: Arc::new(Semaphore::new(
+                config.max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES),
+            )),
+            idle_timeout: Duration::from_secs(
+                config.idle_timeout_secs.unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+            ),
+            throttle,
+        };
+
+        let audit = config.audit_log_path.as_ref().and_then(|path| {
+            AuditLog::open(PathBuf::from(path))
+                .inspect_err(|e| warn!(error = %e, "Not recording data loss to an audit log"))
+                .ok()
+        });
 
         Self {
             watcher_rx,
@@ -35,13 +95,40 @@ impl TailerManager {
             cancel,
             tailers: HashMap::new(),
             checkpoint,
-            output: output_tx,
+            context,
+            drain_deadline: Duration::from_secs(
+                config.drain_deadline_secs.unwrap_or(DEFAULT_DRAIN_DEADLINE_SECS),
+            ),
+            quarantine: Quarantine::new(
+                config
+                    .quarantine_after_crashes
+                    .unwrap_or(DEFAULT_QUARANTINE_AFTER_CRASHES),
+                Duration::from_secs(
+                    config
+                        .quarantine_retry_secs
+                        .unwrap_or(DEFAULT_QUARANTINE_RETRY_SECS),
+                ),
+            ),
+            audit,
+            output_rx: Some(output_rx),
         }
     }
 
+    /// Take the receiving end of the channel all Tailers send their `TailerPayload`s to,
+    /// for the next stage of the pipeline. The channel is bounded, so a stage that falls
+    /// behind pauses the Tailers' reads until it catches up.
+    pub fn take_output(&mut self) -> Option<mpsc::Receiver<TailerPayload>> {
+        self.output_rx.take()
+    }
+
     /// Continuously receive `WatcherEvent`s from the Watcher and manage the pipeline's
     /// `Tailer`s based on them. This is the main orchestration loop for all Tailers
     pub async fn run(mut self) -> Result<()> {
+        self.resume_tailers();
+
+        let mut reap_ticker = interval(REAP_INTERVAL);
+        reap_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
             tokio::select! {
                 _ = self.cancel.cancelled() => {
@@ -52,16 +139,194 @@ impl TailerManager {
                     break;
                 },
 
-                Ok(payload) = self.watcher_rx.recv() => {
+                _ = reap_ticker.tick() => {
+                    self.reap().await;
+                }
+
+                Some(payload) = self.watcher_rx.recv() => {
                     let manager_cancel = &self.cancel.clone();
 
                     for event in translate_event(payload) {
-                        handle_event(event, &mut self.tailers, self.output.clone(), manager_cancel).await;
+                        handle_event(
+                            event,
+                            &mut self.tailers,
+                            &mut self.quarantine,
+                            &self.context,
+                            manager_cancel,
+                        ).await;
                     }
                 }
             }
         }
 
+        self.drain().await;
+
         Ok(())
     }
+
+    /// Start a Tailer for every data file in the `Checkpoint` that isn't retired or skipped
+    /// and still exists at its recorded path, at its recorded offset. Tailing resumes right away on
+    /// startup instead of after the Watchers have rediscovered every data file, which the
+    /// Watchers don't report again for data files they already track.
+    fn resume_tailers(&mut self) {
+        let mut resumed = 0;
+
+        for (id, state) in &self.checkpoint.files {
+            if state.retired || state.skipped || self.quarantine.is_waiting(*id) {
+                continue;
+            }
+
+            let Ok(metadata) = std::fs::metadata(&state.path) else {
+                continue;
+            };
+
+            if metadata.dev() != id.dev || metadata.ino() != id.inode {
+                continue;
+            }
+
+            start_tailer(
+                *id,
+                state.path.clone(),
+                state.offset,
+                &mut self.tailers,
+                &self.context,
+                &self.cancel,
+            );
+            resumed += 1;
+        }
+
+        info!(resumed, tracked = self.checkpoint.files.len(), "Resumed Tailers from the Checkpoint");
+    }
+
+    /// Collect `Tailer`s that finished on their own. A Tailer that panicked or stopped
+    /// with an error is restarted at the last offset downstream accepted once its backoff
+    /// has passed, unless its data file has now failed it often enough in a row to be
+    /// quarantined. Quarantined data files whose quarantine has passed get a new Tailer at
+    /// the offset their last Tailer got to. See `Quarantine`.
+    ///
+    /// Each Tailer is its own task, so a panic never reaches the `TailerManager` or
+    /// other Tailers, it only surfaces here through the Tailer's `JoinHandle`.
+    async fn reap(&mut self) {
+        let finished: Vec<FileId> = self
+            .tailers
+            .iter()
+            .filter(|(_, handle)| handle.join.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in finished {
+            let Some(TailerHandle { join, path, offset, .. }) = self.tailers.remove(&id) else {
+                continue;
+            };
+
+            let offset = offset.load(Ordering::Relaxed);
+
+            let reason = match join.await {
+                // stopped for good, e.g., retired, there is nothing left to restart
+                Ok(Ok(())) => {
+                    self.quarantine.clear(id);
+                    continue;
+                }
+
+                Ok(Err(e)) => {
+                    let e = VesError::Tail(e);
+                    warn!(
+                        ?id,
+                        path = %path.display(),
+                        offset,
+                        category = e.category(),
+                        error = %e,
+                        "Tailer stopped with an error"
+                    );
+                    e.to_string()
+                }
+
+                Err(e) if e.is_panic() => {
+                    let reason = panic_message(e.into_panic());
+                    error!(?id, path = %path.display(), offset, reason = %reason, "Tailer panicked");
+                    reason
+                }
+
+                Err(_) => continue,
+            };
+
+            if self.quarantine.record_failure(id, path.clone(), offset, Instant::now()) {
+                error!(
+                    ?id,
+                    path = %path.display(),
+                    offset,
+                    retry_secs = self.quarantine.retry_after.as_secs(),
+                    quarantined = self.quarantine.quarantined_count(),
+                    "Tailer keeps failing, quarantining data file"
+                );
+
+                if let Some(audit) = &self.audit {
+                    audit
+                        .record(AuditEvent::Quarantined {
+                            file: AuditedFile { id, path, offset },
+                            failures: self.quarantine.max_failures,
+                            retry_secs: self.quarantine.retry_after.as_secs(),
+                            error: reason,
+                        })
+                        .await;
+                }
+            }
+        }
+
+        for (id, path, offset) in self.quarantine.due_for_retry(Instant::now()) {
+            info!(?id, path = %path.display(), offset, "Restarting Tailer of data file");
+            start_tailer(id, path, offset, &mut self.tailers, &self.context, &self.cancel);
+        }
+
+        self.quarantine.forget_recovered(&self.tailers);
+    }
+
+    /// Stop all running `Tailer`s and give them until `drain_deadline` to finish
+    /// sending their in-flight `TailerPayload`s downstream. Tailers still running
+    /// once the deadline passes are aborted, and how many were left behind is
+    /// logged so an unclean shutdown is visible, along with where each of them
+    /// was in the `AuditLog`.
+    async fn drain(&mut self) {
+        for handle in self.tailers.values() {
+            handle.cancel.cancel();
+        }
+
+        let deadline = Instant::now() + self.drain_deadline;
+        let total = self.tailers.len();
+        let mut left_behind = Vec::new();
+
+        for (id, mut handle) in self.tailers.drain() {
+            if timeout_at(deadline, &mut handle.join).await.is_err() {
+                handle.join.abort();
+
+                warn!(?id, "Tailer did not finish before the drain deadline, aborting");
+
+                left_behind.push(AuditedFile {
+                    id,
+                    path: handle.path,
+                    offset: handle.offset.load(Ordering::Relaxed),
+                });
+            }
+        }
+
+        if !left_behind.is_empty() {
+            warn!(
+                total,
+                left_behind = left_behind.len(),
+                deadline_secs = self.drain_deadline.as_secs(),
+                "TailerManager drain deadline passed, some Tailers were aborted"
+            );
+
+            if let Some(audit) = &self.audit {
+                audit
+                    .record(AuditEvent::DrainDeadline {
+                        total,
+                        files: left_behind,
+                    })
+                    .await;
+            }
+        } else {
+            info!(total, "TailerManager drained all Tailers");
+        }
+    }
 }
@@ -5,3 +5,5 @@ pub mod tailer_events;
 pub mod payload;
 pub mod async_read;
 pub mod reader;
+pub mod quarantine;
+pub mod audit;
@@ -1,20 +1,26 @@
 // Local crates
-use crate::watcher::models::{Checkpoint, WatcherPayload};
+use crate::helpers::throttle::Throttle;
+use crate::watcher::{
+    models::{Checkpoint, FileId, WatcherPayload},
+    store::CheckpointUpdate,
+};
 use crate::tailer::async_read::ReadUntil;
 
 // External crates
 use anyhow::Result;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use tokio::fs::File;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use tokio::sync::{broadcast, mpsc};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicU64;
+use tokio::sync::{Semaphore, broadcast, mpsc, watch};
 use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
-/// File inode type-aliasing
-pub type Inode = u64;
-
 /// Control plane for all Tailers, all running Tailers' actions, **`i.e, creation, deletion, stop, shutdown, restart, etc.`** are guided by this manager.
 /// Using `TailerEvent`s the TailerManager is responsible for managing the lifecycle
 /// of all Tailers separately and independently. `WatcherEvent`s are received by
@@ -22,42 +28,144 @@ pub type Inode = u64;
 /// to the WatcherEvent via the `Checkpoint`
 ///
 /// `Tailer`s are stored in the TailerManager and identified via their individual `TailerHandle` value
-/// and the `Inode` value of the file they are tailing.
+/// and the `FileId` (device + inode) of the file they are tailing.
 ///
 /// ```
 /// WatcherEvent -> TailerManager -> TailerEvent -> Tailer
 /// ```
 ///
 pub struct TailerManager {
-    pub watcher_rx: broadcast::Receiver<WatcherPayload>,
+    pub watcher_rx: mpsc::Receiver<WatcherPayload>,
     pub shutdown_rx: broadcast::Receiver<()>,
     pub cancel: CancellationToken,
-    pub tailers: HashMap<Inode, TailerHandle>,
+    pub tailers: HashMap<FileId, TailerHandle>,
     pub checkpoint: Checkpoint,
+    pub context: TailerContext,
+    pub drain_deadline: Duration,
+    pub quarantine: Quarantine,
+    pub audit: Option<AuditLog>,
+    pub output_rx: Option<mpsc::Receiver<TailerPayload>>,
+}
+
+/// Everything a spawned `Tailer` shares with its `TailerManager` and every other Tailer,
+/// i.e., the single `TailerPayload` channel, the `CheckpointWriter` and the file
+/// descriptor budget.
+///
+/// Each Tailer holds a permit from `fd_budget` for as long as its data file is open, so
+/// at most *max_open_files* data files are open at once. Tailers close their data file
+/// after `idle_timeout` without new data, handing their permit to Tailers with work to do.
+/// While `throttle` is engaged, Tailers pause between chunks.
+#[derive(Clone)]
+pub struct TailerContext {
     pub output: mpsc::Sender<TailerPayload>,
+    pub checkpoint_updates: mpsc::Sender<CheckpointUpdate>,
+    pub fd_budget: Arc<Semaphore>,
+    pub idle_timeout: Duration,
+    pub throttle: Throttle,
 }
 
 /// Control plane object that represents an individual running `Tailer` task. Allows `TailerManager`
 /// to have control over an individual Tailer.
+///
+/// `offset` follows the Tailer's offset as downstream accepts its chunks, so a Tailer that
+/// panicked is restarted where it left off rather than at the start of its data file.
+/// `renamed` tells the Tailer its data file's new path after a rename.
 pub struct TailerHandle {
     pub join: JoinHandle<Result<()>>,
     pub cancel: CancellationToken,
+    pub path: PathBuf,
+    pub offset: Arc<AtomicU64>,
+    pub renamed: watch::Sender<PathBuf>,
+}
+
+/// Data files whose `Tailer` keeps failing, by panicking, e.g., on data a parser can't
+/// handle, or by stopping with an error. A failed Tailer is restarted after a backoff
+/// that doubles with every failure in a row. A data file is quarantined once its Tailer
+/// has failed `max_failures` times in a row, and is skipped until `retry_after` has
+/// passed, then its Tailer is given another chance.
+pub struct Quarantine {
+    pub entries: HashMap<FileId, QuarantineEntry>,
+    pub max_failures: u32,
+    pub retry_after: Duration,
+}
+
+/// Failure history of a single data file, see `Quarantine`. `offset` is where its Tailer
+/// last got to, and where it is restarted from, at `restart_at` after a failure or at
+/// `quarantined_until` once it is quarantined.
+pub struct QuarantineEntry {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub failures: u32,
+    pub restart_at: Option<Instant>,
+    pub quarantined_until: Option<Instant>,
+}
+
+/// Append-only NDJSON record of every time the `TailerManager` deliberately gives up on
+/// data, so a postmortem can tell what wasn't shipped and why rather than piecing it
+/// together from logs. Each line is an `AuditRecord`.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    pub path: PathBuf,
+    pub file: Arc<Mutex<std::fs::File>>,
+}
+
+/// A single line of the `AuditLog`, `at` is when the data was given up on
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Why data was given up on, see `AuditLog`
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// The Tailers of `files`, out of the `total` running on shutdown, didn't finish
+    /// before the drain deadline. Whatever they read past `offset` of their data file was
+    /// never accepted downstream.
+    DrainDeadline {
+        total: usize,
+        files: Vec<AuditedFile>,
+    },
+    /// A data file's Tailer failed `failures` times in a row, the last time with `error`.
+    /// Nothing past `offset` is read until its quarantine passes after `retry_secs`
+    Quarantined {
+        #[serde(flatten)]
+        file: AuditedFile,
+        failures: u32,
+        retry_secs: u64,
+        error: String,
+    },
+}
+
+/// A data file in an `AuditRecord`, `offset` is how far downstream had accepted it
+#[derive(Debug, Serialize)]
+pub struct AuditedFile {
+    pub id: FileId,
+    pub path: PathBuf,
+    pub offset: u64,
 }
 
 /// Control plane translations for possible received `WatcherEvent`s. These allow the
 /// `TailerManager` to determine what action an individual Tailer should take based on a certain WatcherEvent.
 pub enum TailerEvent {
     Start {
-        inode: Inode,
+        id: FileId,
         path: PathBuf,
+        offset: u64,
     },
     Stop {
-        inode: Inode,
+        id: FileId,
         path: PathBuf,
     },
     Rotate {
-        old_inode: Inode,
-        new_inode: Inode,
+        old_id: FileId,
+        new_id: FileId,
+        path: PathBuf,
+    },
+    Rename {
+        id: FileId,
         path: PathBuf,
     },
 }
@@ -66,10 +174,17 @@ pub enum TailerEvent {
 /// All Tailers map to a single data file 1:1, and their lifecycle is managed based on
 /// events happening on the file a Tailer is mapped to
 pub struct Tailer {
-    pub inode: Inode,
+    pub id: FileId,
     pub path: PathBuf,
     pub offset: u64,
+    pub acknowledged: Arc<AtomicU64>,
+    pub renamed: watch::Receiver<PathBuf>,
+    pub rotated: bool,
     pub output: mpsc::Sender<TailerPayload>,
+    pub checkpoint_updates: mpsc::Sender<CheckpointUpdate>,
+    pub fd_budget: Arc<Semaphore>,
+    pub idle_timeout: Duration,
+    pub throttle: Throttle,
     pub cancel: CancellationToken,
 }
 
@@ -86,5 +201,5 @@ pub struct TailerPayload {
 /// incrementally without re-opening the source.
 pub struct TailerReader<F> {
     pub reader: ReadUntil<File, F>,
-    pub buffer: Vec<u8>,
+    pub buffer: BytesMut,
 }
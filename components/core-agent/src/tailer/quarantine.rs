@@ -0,0 +1,285 @@
+// Local crates
+use crate::tailer::models::{Quarantine, QuarantineEntry, TailerHandle};
+use crate::watcher::models::FileId;
+
+// External crates
+use std::any::Any;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use tokio::time::{Duration, Instant};
+
+/// Backoff before a failed Tailer is restarted after its first failure in a row
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+impl Quarantine {
+    pub fn new(max_failures: u32, retry_after: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_failures,
+            retry_after,
+        }
+    }
+
+    /// Whether the data file's Tailer must not be started now, because the data file is
+    /// quarantined or its failed Tailer is waiting out its backoff
+    pub fn is_waiting(&self, id: FileId) -> bool {
+        self.entries
+            .get(&id)
+            .is_some_and(|entry| entry.restart_at.is_some() || entry.quarantined_until.is_some())
+    }
+
+    /// Record a failure of the Tailer of a data file at `offset`, at `now`. Returns `true`
+    /// if the data file is quarantined as a result, `false` if its Tailer is restarted
+    /// once its backoff has passed, see [`Quarantine::due_for_retry`].
+    ///
+    /// Failures are only forgotten once a restarted Tailer reads past the offset its data
+    /// file failed at, see [`Quarantine::forget_recovered`], so a data file that still
+    /// fails its Tailer after its quarantine is quarantined again on the next failure.
+    pub fn record_failure(&mut self, id: FileId, path: PathBuf, offset: u64, now: Instant) -> bool {
+        let entry = self.entries.entry(id).or_insert(QuarantineEntry {
+            path: path.clone(),
+            offset,
+            failures: 0,
+            restart_at: None,
+            quarantined_until: None,
+        });
+
+        entry.path = path;
+        entry.offset = offset;
+        entry.failures += 1;
+
+        if entry.failures >= self.max_failures {
+            entry.restart_at = None;
+            entry.quarantined_until = Some(now + self.retry_after);
+            return true;
+        }
+
+        entry.restart_at = Some(now + self.backoff(entry.failures));
+        false
+    }
+
+    /// Backoff before restarting a Tailer after `failures` failures in a row, doubling
+    /// with every failure up to `retry_after`
+    fn backoff(&self, failures: u32) -> Duration {
+        RESTART_BACKOFF
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.retry_after.max(RESTART_BACKOFF))
+    }
+
+    /// Retry a renamed data file at its new path
+    pub fn rename(&mut self, id: FileId, path: PathBuf) {
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.path = path;
+        }
+    }
+
+    /// Forget a data file, e.g., once it is removed or rotated away
+    pub fn clear(&mut self, id: FileId) {
+        self.entries.remove(&id);
+    }
+
+    /// Take the data files whose backoff or quarantine has passed by `now`, with the
+    /// offset their Tailer last got to. Their Tailers are started again there by the
+    /// caller.
+    pub fn due_for_retry(&mut self, now: Instant) -> Vec<(FileId, PathBuf, u64)> {
+        self.entries
+            .iter_mut()
+            .filter(|(_, entry)| {
+                entry
+                    .restart_at
+                    .or(entry.quarantined_until)
+                    .is_some_and(|until| until <= now)
+            })
+            .map(|(id, entry)| {
+                entry.restart_at = None;
+                entry.quarantined_until = None;
+                (*id, entry.path.clone(), entry.offset)
+            })
+            .collect()
+    }
+
+    /// Forget the failures of data files whose restarted Tailer has read past the offset
+    /// it failed at, it recovered
+    pub fn forget_recovered(&mut self, tailers: &HashMap<FileId, TailerHandle>) {
+        self.entries.retain(|id, entry| {
+            let recovered = tailers
+                .get(id)
+                .is_some_and(|handle| handle.offset.load(Ordering::Relaxed) > entry.offset);
+
+            !recovered
+        });
+    }
+
+    /// Number of data files currently quarantined
+    pub fn quarantined_count(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|entry| entry.quarantined_until.is_some())
+            .count()
+    }
+}
+
+/// Best-effort message of a Tailer's panic, for logging
+pub fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+
+    if let Some(message) = panic.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown panic".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::{Result, ensure};
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicU64;
+    use tokio::sync::watch;
+    use tokio_util::sync::CancellationToken;
+
+    const APP_LOG: FileId = FileId { dev: 1, inode: 10 };
+
+    fn app_log() -> PathBuf {
+        PathBuf::from("/var/log/app/app.log")
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_retry_interval() -> Result<()> {
+        let mut quarantine = Quarantine::new(10, Duration::from_secs(4));
+        let now = Instant::now();
+
+        let mut backoffs = Vec::new();
+        for _ in 0..4 {
+            let quarantined = quarantine.record_failure(APP_LOG, app_log(), 0, now);
+            ensure!(!quarantined, "expected a restart rather than a quarantine");
+
+            let restart_at = quarantine.entries.get(&APP_LOG).and_then(|entry| entry.restart_at);
+            backoffs.push(restart_at.map(|restart_at| restart_at - now));
+        }
+
+        let expected = [1, 2, 4, 4].map(|secs| Some(Duration::from_secs(secs)));
+        ensure!(backoffs == expected, "expected backoffs of {expected:?}, got {backoffs:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn data_file_is_quarantined_after_max_failures() -> Result<()> {
+        let mut quarantine = Quarantine::new(3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        let quarantined: Vec<bool> = (0..3)
+            .map(|_| quarantine.record_failure(APP_LOG, app_log(), 0, now))
+            .collect();
+
+        ensure!(
+            quarantined == [false, false, true],
+            "expected the third failure to quarantine app.log, got {quarantined:?}"
+        );
+        ensure!(
+            quarantine.is_waiting(APP_LOG) && quarantine.quarantined_count() == 1,
+            "expected app.log to wait out its quarantine"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn retry_happens_once_due_at_the_latest_path_and_offset() -> Result<()> {
+        let mut quarantine = Quarantine::new(3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        quarantine.record_failure(APP_LOG, app_log(), 42, now);
+        quarantine.rename(APP_LOG, PathBuf::from("/var/log/app/app.log.1"));
+
+        let early = quarantine.due_for_retry(now + Duration::from_millis(500));
+        ensure!(early.is_empty(), "expected no retry during the backoff, got {early:?}");
+
+        let due = quarantine.due_for_retry(now + RESTART_BACKOFF);
+        ensure!(
+            due == vec![(APP_LOG, PathBuf::from("/var/log/app/app.log.1"), 42)],
+            "expected app.log to be retried at its new path and offset, got {due:?}"
+        );
+        ensure!(
+            !quarantine.is_waiting(APP_LOG) && quarantine.entries.contains_key(&APP_LOG),
+            "expected app.log to be startable while its failures are remembered"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cleared_data_file_is_forgotten() -> Result<()> {
+        let mut quarantine = Quarantine::new(1, Duration::from_secs(60));
+
+        quarantine.record_failure(APP_LOG, app_log(), 0, Instant::now());
+        quarantine.clear(APP_LOG);
+
+        ensure!(
+            !quarantine.is_waiting(APP_LOG) && quarantine.entries.is_empty(),
+            "expected app.log to be forgotten"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failures_are_forgotten_once_the_tailer_reads_past_them() -> Result<()> {
+        let mut quarantine = Quarantine::new(3, Duration::from_secs(60));
+        quarantine.record_failure(APP_LOG, app_log(), 100, Instant::now());
+
+        let offset = Arc::new(AtomicU64::new(100));
+        let (renamed, _) = watch::channel(app_log());
+        let mut tailers = HashMap::new();
+        tailers.insert(
+            APP_LOG,
+            TailerHandle {
+                join: tokio::spawn(async { Ok(()) }),
+                cancel: CancellationToken::new(),
+                path: app_log(),
+                offset: offset.clone(),
+                renamed,
+            },
+        );
+
+        quarantine.forget_recovered(&tailers);
+        ensure!(
+            quarantine.entries.contains_key(&APP_LOG),
+            "expected the failures to be kept at the offset app.log failed at"
+        );
+
+        offset.store(101, Ordering::Relaxed);
+
+        quarantine.forget_recovered(&tailers);
+        ensure!(
+            quarantine.entries.is_empty(),
+            "expected the failures to be forgotten past the offset app.log failed at"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn panic_message_is_recovered_from_the_payload() -> Result<()> {
+        let messages = [
+            panic_message(Box::new("static message")),
+            panic_message(Box::new(String::from("formatted message"))),
+            panic_message(Box::new(42)),
+        ];
+
+        ensure!(
+            messages == ["static message", "formatted message", "unknown panic"],
+            "expected each payload's message, got {messages:?}"
+        );
+
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use crate::tailer::async_read::CustomAsyncReadExt;
 use crate::tailer::models::TailerReader;
 
 // External crates
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
@@ -23,20 +23,26 @@ where
     ) -> Self {
         Self {
             reader: file.read_until_future(stop),
-            buffer: vec![0u8; READ_BUFFER_SIZE],
+            buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
         }
     }
 
+    /// Read the next chunk of data from the source. The read bytes are split
+    /// off the reader's buffer and frozen, so the returned `Bytes` shares the
+    /// buffer's allocation instead of copying it. Once downstream drops the
+    /// chunk, `reserve` reclaims the same allocation for the next read.
     pub async fn read_data_chunk(
         &mut self,
     ) -> std::io::Result<Option<Bytes>> {
-        let n = self.reader.read(&mut self.buffer).await?;
+        self.buffer.reserve(READ_BUFFER_SIZE);
+
+        let n = self.reader.read_buf(&mut self.buffer).await?;
 
         if n == 0 {
             return Ok(None);
         }
 
-        let chunk = Bytes::copy_from_slice(&self.buffer[..n]);
+        let chunk = self.buffer.split().freeze();
 
         Ok(Some(chunk))
     }
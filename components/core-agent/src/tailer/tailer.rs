@@ -1,38 +1,66 @@
 // Local crates
 use crate::tailer::{
     models::{
-        Inode,
         Tailer,
+        TailerContext,
         TailerHandle,
         TailerPayload,
         TailerReader,
     },
     payload::build_payload,
 };
+use crate::watcher::{models::FileId, store::CheckpointUpdate};
 
 // External crates
 use anyhow::Result;
+use std::io::{ErrorKind, SeekFrom};
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncSeekExt;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant, sleep, timeout};
 use tokio_util::sync::CancellationToken;
 use tokio::fs::File;
 use std::pin::pin;
+use tracing::debug;
+
+/// How often a Tailer checks its data file for new data, both while the data file is
+/// open and at EOF, and while it is closed after being idle
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a Tailer whose data file is gone from its path waits for the Watcher to
+/// report where it was renamed to before retiring it
+const ROTATION_GRACE: Duration = Duration::from_secs(5);
+
+/// Chunks a Tailer reads in a row before it hands its file descriptor permit to Tailers
+/// waiting for one, if the budget is used up
+const CHUNKS_PER_TURN: usize = 1024;
 
 impl Tailer {
-    /// Create a new individual Tailer for a specific file(inode)
+    /// Create a new individual Tailer for a specific file(device + inode)
     pub fn new(
-        inode: Inode,
+        id: FileId,
         path: PathBuf,
         offset: u64,
-        output: mpsc::Sender<TailerPayload>,
+        renamed: watch::Receiver<PathBuf>,
+        context: &TailerContext,
         cancel: CancellationToken,
     ) -> Self {
         Self {
-            inode,
+            id,
             path,
             offset,
-            output,
+            acknowledged: Arc::new(AtomicU64::new(offset)),
+            renamed,
+            rotated: false,
+            output: context.output.clone(),
+            checkpoint_updates: context.checkpoint_updates.clone(),
+            fd_budget: context.fd_budget.clone(),
+            idle_timeout: context.idle_timeout,
+            throttle: context.throttle.clone(),
             cancel,
         }
     }
@@ -40,62 +68,277 @@ impl Tailer {
     /// Run loop for an already spawned/created `Tailer`, where the lifecycle
     /// which is managed by the `TailerManager`, `Payload` transmission, and
     /// management for an individual running Tailer takes place.
-    pub async fn run(self) -> Result<()> {
-        let file = File::open(&self.path).await?;
-        let stop_condition = pin!(self.cancel.cancelled());
+    ///
+    /// The Tailer's offset is reported to the `CheckpointWriter` after every chunk, the
+    /// writer batches these so a busy Tailer doesn't cause a checkpoint write per read.
+    ///
+    /// A Tailer only keeps its data file open, and holds a permit of the file descriptor
+    /// budget, while there is data to read. Once the data file has been idle for
+    /// `idle_timeout` it is closed, and reopened at the same offset when it grows again.
+    /// While the budget is used up, a busy Tailer closes its data file every
+    /// `CHUNKS_PER_TURN` chunks and queues for a permit again, so with more busy data
+    /// files than permits every data file still gets its turn.
+    ///
+    /// A data file that is renamed, e.g., `app.log` -> `app.log.1` by logrotate, was
+    /// rotated away from the path it was tailed under. It is read at its new path until it
+    /// goes idle, giving its writer time to move on to the new data file, and then retired
+    /// in the `Checkpoint`, so its Tailer isn't resumed on restart.
+    pub async fn run(mut self) -> Result<()> {
+        loop {
+            // a Tailer cancelled before it got to run never touches its data file
+            let permit = tokio::select! {
+                biased;
+                _ = self.cancel.cancelled() => return Ok(()),
+                permit = self.fd_budget.clone().acquire_owned() => permit?,
+            };
+
+            let yielded = self.read_until_idle().await?;
+            drop(permit);
+
+            if self.cancel.is_cancelled() {
+                return Ok(());
+            }
+
+            // the semaphore is fair, Tailers already waiting get a permit first
+            if yielded {
+                continue;
+            }
+
+            if self.rotated {
+                debug!(path = %self.path.display(), "Rotated data file drained, retiring it");
+                self.retire().await?;
+                return Ok(());
+            }
+
+            if !self.wait_for_activity().await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Open the data file at the Tailer's offset and read it until the Tailer is cancelled
+    /// or no new data shows up for `idle_timeout`. The data file is closed on return.
+    ///
+    /// Returns `true` if the Tailer stopped reading after `CHUNKS_PER_TURN` chunks because
+    /// the file descriptor budget is used up, with data possibly left to read.
+    async fn read_until_idle(&mut self) -> Result<bool> {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            // rotated away or removed in the meantime, see `wait_for_activity`
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !self.is_same_file(&file.metadata().await?) {
+            return Ok(false);
+        }
+
+        file.seek(SeekFrom::Start(self.offset)).await?;
+
+        let cancel = self.cancel.clone();
+        let stop_condition = pin!(cancel.cancelled());
         let mut reader = TailerReader::new(file, stop_condition);
+        let mut last_read = Instant::now();
+        let mut chunks = 0;
 
         loop {
             match reader.read_data_chunk().await? {
                 Some(read_data) => {
+                    last_read = Instant::now();
+                    let read_len = read_data.len() as u64;
+
+                    // waits while downstream is full, which pauses reading this data file,
+                    // the offset only moves once downstream has accepted the chunk
                     let tailer_payload = build_payload(read_data);
-                    send_payload_downstream(tailer_payload, &self.output);
+                    if send_payload_downstream(tailer_payload, &self.output).await.is_err() {
+                        debug!(path = %self.path.display(), "Downstream closed, stopping Tailer");
+                        return Ok(false);
+                    }
+
+                    self.set_offset(self.offset + read_len);
+
+                    self.checkpoint_updates
+                        .send(CheckpointUpdate::Offset { id: self.id, offset: self.offset })
+                        .await?;
+
+                    self.throttle.pause_if_engaged().await;
+
+                    chunks += 1;
+                    if chunks >= CHUNKS_PER_TURN && self.fd_budget.available_permits() == 0 {
+                        debug!(path = %self.path.display(), "File descriptor budget used up, yielding to other Tailers");
+                        return Ok(true);
+                    }
                 }
-                None => break,
+                None => {
+                    if self.cancel.is_cancelled() {
+                        return Ok(false);
+                    }
+
+                    // the open data file is still read after a rename, only its path changed
+                    self.follow_rename();
+
+                    if last_read.elapsed() >= self.idle_timeout {
+                        debug!(path = %self.path.display(), "Closing idle data file");
+                        return Ok(false);
+                    }
+
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Wait until the closed data file grows past the Tailer's offset. Returns `false` if
+    /// the Tailer is cancelled first. A data file that shrank below the offset was truncated
+    /// and is read from the start.
+    ///
+    /// If the path no longer points at the data file this Tailer belongs to, or at nothing,
+    /// the data file was rotated away or removed. Its Watcher reports where a rotated data
+    /// file went, and a removal stops the Tailer, see [`Tailer::await_rename`].
+    async fn wait_for_activity(&mut self) -> Result<bool> {
+        loop {
+            tokio::select! {
+                _ = self.cancel.cancelled() => return Ok(false),
+                _ = sleep(POLL_INTERVAL) => {}
+            }
+
+            // read whatever was appended before the rename at the new path
+            if self.follow_rename() {
+                return Ok(true);
             }
+
+            let metadata = match tokio::fs::metadata(&self.path).await {
+                Ok(metadata) if self.is_same_file(&metadata) => metadata,
+                Ok(_) => return self.await_rename().await,
+                Err(e) if e.kind() == ErrorKind::NotFound => return self.await_rename().await,
+                Err(e) => return Err(e.into()),
+            };
+
+            if metadata.len() < self.offset {
+                self.set_offset(0);
+            }
+
+            if metadata.len() > self.offset {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Wait up to `ROTATION_GRACE` for the Watcher to report the new path of a data file
+    /// that is gone from the Tailer's path. Returns `true` once it has, so the data file is
+    /// drained at its new path. Otherwise the data file can't be found anymore and is
+    /// retired at the Tailer's offset.
+    async fn await_rename(&mut self) -> Result<bool> {
+        let renamed = tokio::select! {
+            _ = self.cancel.cancelled() => return Ok(false),
+            changed = timeout(ROTATION_GRACE, self.renamed.changed()) => matches!(changed, Ok(Ok(()))),
+        };
+
+        if renamed {
+            self.take_rename();
+            return Ok(true);
+        }
+
+        debug!(path = %self.path.display(), "Data file is gone from its path, retiring it");
+        self.retire().await?;
+
+        Ok(false)
+    }
+
+    /// Pick up a new path reported for the data file since the last check, see
+    /// [`Tailer::take_rename`]. Returns whether there was one.
+    fn follow_rename(&mut self) -> bool {
+        if !self.renamed.has_changed().unwrap_or(false) {
+            return false;
         }
 
+        self.take_rename();
+        true
+    }
+
+    /// Tail the data file at the path its Watcher reported after a rename, from now on it
+    /// is drained and retired
+    fn take_rename(&mut self) {
+        self.path = self.renamed.borrow_and_update().clone();
+        self.rotated = true;
+
+        debug!(path = %self.path.display(), "Data file was renamed, draining it");
+    }
+
+    /// Retire the data file at the Tailer's offset, see `FileState::retired`
+    async fn retire(&self) -> Result<()> {
+        self.checkpoint_updates
+            .send(CheckpointUpdate::Retire { id: self.id, offset: self.offset })
+            .await?;
+
         Ok(())
     }
+
+    /// Move the Tailer's offset, and with it where the `TailerManager` restarts it from
+    fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+        self.acknowledged.store(offset, Ordering::Relaxed);
+    }
+
+    fn is_same_file(&self, metadata: &std::fs::Metadata) -> bool {
+        metadata.dev() == self.id.dev && metadata.ino() == self.id.inode
+    }
 }
 
 pub fn start_tailer(
-    inode: u64,
+    id: FileId,
     path: PathBuf,
-    tailers: &mut HashMap<Inode, TailerHandle>,
-    output: mpsc::Sender<TailerPayload>,
+    offset: u64,
+    tailers: &mut HashMap<FileId, TailerHandle>,
+    context: &TailerContext,
     cancel: &CancellationToken,
 ) {
-    if tailers.contains_key(&inode) {
+    if tailers.contains_key(&id) {
         return;
     }
 
     let tailer_cancel = cancel.child_token();
+    let (renamed, renamed_rx) = watch::channel(path.clone());
 
     let new_tailer = Tailer::new(
-        inode,
-        path,
-        0,
-        output.clone(),
+        id,
+        path.clone(),
+        offset,
+        renamed_rx,
+        context,
         tailer_cancel.clone(),
     );
 
+    let offset = new_tailer.acknowledged.clone();
+
     let handle = tokio::task::spawn(
         new_tailer.run()
     );
 
     tailers.insert(
-        inode, TailerHandle { join: handle, cancel: tailer_cancel }
+        id, TailerHandle { join: handle, cancel: tailer_cancel, path, offset, renamed }
     );
 
     return;
 }
 
+/// Point the Tailer of a renamed data file at the data file's new path, see [`Tailer::run`]
+pub fn rename_tailer(
+    id: FileId,
+    path: PathBuf,
+    tailers: &mut HashMap<FileId, TailerHandle>,
+) {
+    if let Some(tailer_handle) = tailers.get_mut(&id) {
+        tailer_handle.path = path.clone();
+        tailer_handle.renamed.send_replace(path);
+    }
+}
+
 pub fn stop_tailer(
-    inode: u64,
-    tailers: &mut HashMap<Inode, TailerHandle>,
+    id: FileId,
+    tailers: &mut HashMap<FileId, TailerHandle>,
 ) {
-    if let Some(tailer_handle) = tailers.remove(&inode) {
+    if let Some(tailer_handle) = tailers.remove(&id) {
         tailer_handle.cancel.cancel();
     }
 
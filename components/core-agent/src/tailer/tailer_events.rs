@@ -2,17 +2,19 @@
 use crate::{
     tailer::{
         tailer::{
+            rename_tailer,
             start_tailer,
             stop_tailer,
         },
         models::{
-            Inode,
+            Quarantine,
+            TailerContext,
             TailerHandle,
             TailerEvent,
-            TailerPayload,
         },
     },
     watcher::models::{
+        FileId,
         WatcherPayload,
         WatcherEvent
     },
@@ -20,57 +22,80 @@ use crate::{
 
 // External crates
 use std::collections::HashMap;
-use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
+use tracing::debug;
 
 pub fn translate_event(
     payload: WatcherPayload
 ) -> impl IntoIterator<Item = TailerEvent> {
 
     match payload.event {
-        WatcherEvent::FileDiscovered { inode, path } => {
-            vec![TailerEvent::Start { inode, path }]
+        WatcherEvent::FileDiscovered { id, path } => {
+            vec![TailerEvent::Start { id, path, offset: payload.offset }]
         }
 
-        WatcherEvent::FileRotated {
-            old_inode,
-            new_inode,
-            old_path,
-            new_path,
-        } => {
-            vec![
-                TailerEvent::Stop { inode: old_inode, path: old_path },
-                TailerEvent::Start {
-                    inode: new_inode,
-                    path: new_path,
-                }
-            ]
+        // a renamed data file keeps its Tailer, which drains it at its new path
+        WatcherEvent::FileRotated { old_id, new_id, new_path, .. } if old_id == new_id => {
+            vec![TailerEvent::Rename { id: new_id, path: new_path }]
         }
 
-        WatcherEvent::FileRemoved { inode, path } => {
-            vec![TailerEvent::Stop { inode, path }]
+        WatcherEvent::FileRotated { old_id, new_id, new_path, .. } => {
+            vec![TailerEvent::Rotate { old_id, new_id, path: new_path }]
+        }
+
+        WatcherEvent::FileRemoved { id, path } => {
+            vec![TailerEvent::Stop { id, path }]
         }
     }
 }
 
+/// Apply a `TailerEvent` to the running Tailers. Quarantined data files don't get a
+/// Tailer until their quarantine has passed, nor do data files whose failed Tailer is
+/// backing off before its restart, and data files that go away are dropped
+/// from the `Quarantine`. Starting a data file that is already tailed under a different
+/// path points its Tailer at the new path. A data file rotated away for a different one
+/// keeps its Tailer until it is drained, see `Tailer::await_rename`.
 pub async fn handle_event(
     event: TailerEvent,
-    tailers: &mut HashMap<Inode, TailerHandle>,
-    output: mpsc::Sender<TailerPayload>,
+    tailers: &mut HashMap<FileId, TailerHandle>,
+    quarantine: &mut Quarantine,
+    context: &TailerContext,
     cancel: &CancellationToken,
 ) {
     match event {
-        TailerEvent::Start { inode, path } => {
+        TailerEvent::Start { id, path, offset } => {
+            // a data file found at a new path while it is tailed was renamed
+            if tailers.get(&id).is_some_and(|handle| handle.path != path) {
+                quarantine.rename(id, path.clone());
+                rename_tailer(id, path, tailers);
+                return;
+            }
+
+            if quarantine.is_waiting(id) {
+                debug!(path = %path.display(), "Data file is quarantined or its Tailer is backing off, not starting it");
+                return;
+            }
+
             start_tailer(
-                inode, path, tailers, output, cancel
+                id, path, offset, tailers, context, cancel
             )
         }
-        TailerEvent::Stop { inode, path } => {
-            stop_tailer(inode, tailers)
+        TailerEvent::Stop { id, path: _ } => {
+            quarantine.clear(id);
+            stop_tailer(id, tailers)
+        }
+        TailerEvent::Rotate { old_id, new_id, path } => {
+            // the old Tailer reads what is left of its data file, then finds its path
+            // pointing elsewhere and retires it
+            quarantine.clear(old_id);
+
+            if !quarantine.is_waiting(new_id) {
+                start_tailer(new_id, path, 0, tailers, context, cancel)
+            }
         }
-        TailerEvent::Rotate { old_inode, new_inode, path } => {
-            stop_tailer(old_inode, tailers);
-            start_tailer(new_inode, path, tailers, output, cancel)
+        TailerEvent::Rename { id, path } => {
+            quarantine.rename(id, path.clone());
+            rename_tailer(id, path, tailers)
         }
     }
 }
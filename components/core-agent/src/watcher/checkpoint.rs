@@ -0,0 +1,448 @@
+// Local crates
+use crate::watcher::{
+    models::{Checkpoint, Device, FileId, FileState, Inode},
+    store::CheckpointUpdate,
+};
+
+// External crates
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Current version of the serialized `Checkpoint` layout
+const CHECKPOINT_VERSION: u32 = 2;
+
+impl Checkpoint {
+    /// Insert or replace the `FileState` of a data file, keyed by its identity
+    pub fn upsert(&mut self, state: FileState) {
+        self.files.insert(state.id(), state);
+    }
+
+    /// Apply a single `CheckpointUpdate`. Updates of a single field of a data file that
+    /// isn't tracked are ignored.
+    pub fn apply(&mut self, update: CheckpointUpdate) {
+        match update {
+            CheckpointUpdate::Upsert(state) => self.upsert(state),
+            CheckpointUpdate::Offset { id, offset } => {
+                if let Some(state) = self.files.get_mut(&id) {
+                    state.offset = offset;
+                }
+            }
+            CheckpointUpdate::Retire { id, offset } => {
+                if let Some(state) = self.files.get_mut(&id) {
+                    state.offset = offset;
+                    state.retired = true;
+                }
+            }
+            CheckpointUpdate::Rename { id, path } => {
+                if let Some(state) = self.files.get_mut(&id) {
+                    state.path = path;
+                }
+            }
+            CheckpointUpdate::Seen { id, last_seen } => {
+                if let Some(state) = self.files.get_mut(&id) {
+                    state.last_seen = last_seen;
+                }
+            }
+            CheckpointUpdate::Remove(id) => {
+                self.files.remove(&id);
+            }
+        }
+    }
+
+    /// Identity of the data file currently tracked under `path`, if any
+    pub fn find_by_path(&self, path: &Path) -> Option<FileId> {
+        self.files
+            .values()
+            .find(|state| state.path == path)
+            .map(FileState::id)
+    }
+
+    /// Drop entries whose data files no longer exist. An entry is expired once its data
+    /// file has been missing for longer than `ttl`. If the `Checkpoint` still holds more
+    /// than `max_entries` afterwards, the remaining missing entries are compacted away,
+    /// least recently seen first. Entries of data files that still exist are never dropped.
+    ///
+    /// Entries of data files that still exist are marked as seen now. Those last seen more
+    /// than a quarter of `ttl` ago are listed in `PruneReport::seen`, so the persisted
+    /// `last_seen` is kept recent enough for the TTL without a write on every prune.
+    /// Checks every data file on disk, so it blocks.
+    pub fn prune_stale(&mut self, ttl: Duration, max_entries: Option<usize>) -> PruneReport {
+        let now = Utc::now();
+        let mut report = PruneReport::default();
+        let mut missing = Vec::new();
+
+        self.files.retain(|id, state| {
+            if file_exists(*id, state) {
+                if now - state.last_seen > ttl / 4 {
+                    report.seen.push(*id);
+                }
+
+                state.last_seen = now;
+                return true;
+            }
+
+            if now - state.last_seen > ttl {
+                report.expired += 1;
+                report.removed.push(*id);
+                return false;
+            }
+
+            missing.push((state.last_seen, *id));
+            true
+        });
+
+        if let Some(max_entries) = max_entries {
+            missing.sort_unstable_by_key(|(last_seen, _)| *last_seen);
+
+            for (_, id) in missing {
+                if self.files.len() <= max_entries {
+                    break;
+                }
+
+                self.files.remove(&id);
+                report.compacted += 1;
+                report.removed.push(id);
+            }
+        }
+
+        report.remaining = self.files.len();
+        report
+    }
+}
+
+/// Outcome of [`Checkpoint::prune_stale`]
+#[derive(Debug, Default, Clone)]
+pub struct PruneReport {
+    pub expired: usize,
+    pub compacted: usize,
+    pub remaining: usize,
+    pub removed: Vec<FileId>,
+    pub seen: Vec<FileId>,
+}
+
+impl PruneReport {
+    /// Total number of entries dropped from the `Checkpoint`
+    pub fn pruned(&self) -> usize {
+        self.expired + self.compacted
+    }
+}
+
+impl FileState {
+    /// Identity of the data file this state belongs to
+    pub fn id(&self) -> FileId {
+        FileId {
+            dev: self.dev,
+            inode: self.inode,
+        }
+    }
+}
+
+/// On-disk layouts of a `Checkpoint`. Serialization always writes the current layout,
+/// deserialization also accepts checkpoints written before data files were identified
+/// by device + inode, so upgrading doesn't require deleting existing checkpoints.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CheckpointRepr {
+    Current {
+        version: u32,
+        files: Vec<FileState>,
+    },
+    Legacy {
+        files: HashMap<Inode, FileState>,
+    },
+}
+
+impl From<CheckpointRepr> for Checkpoint {
+    fn from(repr: CheckpointRepr) -> Self {
+        let mut checkpoint = Checkpoint::default();
+
+        match repr {
+            CheckpointRepr::Current { files, .. } => {
+                for state in files {
+                    checkpoint.upsert(state);
+                }
+            }
+
+            CheckpointRepr::Legacy { files } => {
+                for (inode, mut state) in files {
+                    state.inode = inode;
+                    state.dev = legacy_device_for(&state);
+
+                    checkpoint.upsert(state);
+                }
+            }
+        }
+
+        checkpoint
+    }
+}
+
+impl From<Checkpoint> for CheckpointRepr {
+    fn from(checkpoint: Checkpoint) -> Self {
+        CheckpointRepr::Current {
+            version: CHECKPOINT_VERSION,
+            files: checkpoint.files.into_values().collect(),
+        }
+    }
+}
+
+/// A data file exists if its recorded path still points at the same device + inode
+fn file_exists(id: FileId, state: &FileState) -> bool {
+    match fs::metadata(&state.path) {
+        Ok(metadata) => metadata.dev() == id.dev && metadata.ino() == id.inode,
+        Err(_) => false,
+    }
+}
+
+/// Legacy checkpoints didn't record the device of a data file. Recover it from the file
+/// still at the recorded path, as long as it is the same inode, otherwise the entry is
+/// stale and is left with device 0 so it never matches a live data file.
+fn legacy_device_for(state: &FileState) -> Device {
+    match fs::metadata(&state.path) {
+        Ok(metadata) if metadata.ino() == state.inode => metadata.dev(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::{Result, ensure};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Directory of a single test, removed when the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Result<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "ves-{name}-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&path)?;
+
+            Ok(Self(path))
+        }
+
+        fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn legacy_checkpoint_is_keyed_by_device_and_inode() -> Result<()> {
+        let dir = TestDir::new("legacy-checkpoint")?;
+        let live = dir.join("app.log");
+        fs::write(&live, "line\n")?;
+        let metadata = fs::metadata(&live)?;
+
+        let legacy = serde_json::json!({
+            "files": {
+                metadata.ino().to_string(): {
+                    "path": live,
+                    "inode": metadata.ino(),
+                    "offset": 5,
+                },
+                "4242": {
+                    "path": dir.join("gone.log"),
+                    "inode": 4242,
+                    "offset": 42,
+                },
+            }
+        });
+
+        let checkpoint: Checkpoint = serde_json::from_value(legacy)?;
+
+        let live_id = FileId {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+        };
+        let state = checkpoint.files.get(&live_id);
+        ensure!(
+            state.is_some_and(|state| state.offset == 5 && !state.retired && !state.skipped),
+            "expected app.log to be keyed by its device and inode at its offset, got {:?}",
+            checkpoint.files
+        );
+
+        // a data file that is gone can't tell its device, it never matches a live one
+        let gone_id = FileId { dev: 0, inode: 4242 };
+        ensure!(
+            checkpoint.files.get(&gone_id).is_some_and(|state| state.offset == 42),
+            "expected gone.log to be kept under device 0, got {:?}",
+            checkpoint.files
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrated_checkpoint_is_written_in_the_current_layout() -> Result<()> {
+        let legacy = r#"{"files": {"7": {"path": "/nonexistent/app.log", "inode": 7, "offset": 3}}}"#;
+
+        let checkpoint: Checkpoint = serde_json::from_str(legacy)?;
+        let written = serde_json::to_value(&checkpoint)?;
+
+        ensure!(
+            written["version"] == CHECKPOINT_VERSION && written["files"][0]["inode"] == 7,
+            "expected the current layout, got {written}"
+        );
+
+        let reread: Checkpoint = serde_json::from_value(written)?;
+        ensure!(
+            reread.files.contains_key(&FileId { dev: 0, inode: 7 }),
+            "expected the written checkpoint to read back, got {:?}",
+            reread.files
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_checkpoint_is_an_error() -> Result<()> {
+        for corrupt in [
+            r#"{"files": [{"path": "/var/log/app.log"}]}"#,
+            r#"{"version": 2, "files": {"1": "app.log"}}"#,
+            r#"{"files": "#,
+        ] {
+            let result = serde_json::from_str::<Checkpoint>(corrupt);
+
+            ensure!(result.is_err(), "expected {corrupt} to be rejected, got {result:?}");
+        }
+
+        Ok(())
+    }
+
+    fn tracked(path: PathBuf, id: FileId, seen_ago: Duration) -> FileState {
+        FileState {
+            path,
+            dev: id.dev,
+            inode: id.inode,
+            offset: 0,
+            last_seen: Utc::now() - seen_ago,
+            retired: false,
+            skipped: false,
+        }
+    }
+
+    fn file_id(path: &Path) -> Result<FileId> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(FileId {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    #[test]
+    fn prune_expires_missing_entries_past_the_ttl() -> Result<()> {
+        let dir = TestDir::new("prune-ttl")?;
+        let ttl = Duration::hours(24);
+
+        let expired = FileId { dev: 0, inode: 1 };
+        let recent = FileId { dev: 0, inode: 2 };
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(tracked(dir.join("expired.log"), expired, Duration::hours(25)));
+        checkpoint.upsert(tracked(dir.join("recent.log"), recent, Duration::hours(1)));
+
+        let report = checkpoint.prune_stale(ttl, None);
+
+        ensure!(
+            report.expired == 1 && report.removed == vec![expired] && report.remaining == 1,
+            "expected only expired.log to be pruned, got {report:?}"
+        );
+        ensure!(
+            checkpoint.files.contains_key(&recent),
+            "expected recent.log to be kept within the ttl"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_keeps_existing_data_files_and_marks_them_seen() -> Result<()> {
+        let dir = TestDir::new("prune-existing")?;
+        let ttl = Duration::hours(24);
+
+        let old = dir.join("old.log");
+        let fresh = dir.join("fresh.log");
+        fs::write(&old, "line\n")?;
+        fs::write(&fresh, "line\n")?;
+        let (old_id, fresh_id) = (file_id(&old)?, file_id(&fresh)?);
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(tracked(old, old_id, Duration::days(30)));
+        checkpoint.upsert(tracked(fresh, fresh_id, Duration::minutes(1)));
+
+        let report = checkpoint.prune_stale(ttl, Some(0));
+
+        ensure!(
+            report.pruned() == 0 && report.remaining == 2,
+            "expected existing data files to be kept regardless of limits, got {report:?}"
+        );
+        ensure!(
+            report.seen == vec![old_id],
+            "expected only old.log to need its last_seen persisted, got {report:?}"
+        );
+        ensure!(
+            checkpoint
+                .files
+                .get(&old_id)
+                .is_some_and(|state| Utc::now() - state.last_seen < Duration::minutes(1)),
+            "expected old.log to be marked as seen now"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_compacts_least_recently_seen_missing_entries_first() -> Result<()> {
+        let dir = TestDir::new("prune-compact")?;
+        let ttl = Duration::hours(24);
+
+        let live = dir.join("live.log");
+        fs::write(&live, "line\n")?;
+        let live_id = file_id(&live)?;
+
+        let oldest = FileId { dev: 0, inode: 1 };
+        let older = FileId { dev: 0, inode: 2 };
+        let newest = FileId { dev: 0, inode: 3 };
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(tracked(live, live_id, Duration::days(2)));
+        checkpoint.upsert(tracked(dir.join("a.log"), oldest, Duration::hours(3)));
+        checkpoint.upsert(tracked(dir.join("b.log"), older, Duration::hours(2)));
+        checkpoint.upsert(tracked(dir.join("c.log"), newest, Duration::hours(1)));
+
+        let report = checkpoint.prune_stale(ttl, Some(2));
+
+        ensure!(
+            report.compacted == 2 && report.removed == vec![oldest, older],
+            "expected the two least recently seen missing entries to be compacted, got {report:?}"
+        );
+        ensure!(
+            checkpoint.files.contains_key(&live_id) && checkpoint.files.contains_key(&newest),
+            "expected live.log and c.log to be kept, got {:?}",
+            checkpoint.files.keys()
+        );
+
+        Ok(())
+    }
+}
@@ -2,16 +2,35 @@
 use crate::{
     helpers::load_config::WatcherConfig,
     watcher::{
-        models::{Checkpoint, WatcherEvent, WatcherPayload},
-        state::determine_file_state,
+        models::{
+            Checkpoint, EventRecorder, FileId, FileState, RecordedEvent, WatcherEvent,
+            WatcherPayload,
+        },
+        state::file_state,
+        store::CheckpointUpdate,
     },
 };
 
 // External crates
 use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use regex::RegexSet;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
-use walkdir::WalkDir;
+use tracing::{debug, info};
+use walkdir::{DirEntry, WalkDir};
+
+/// Seconds in a day, for *ignore_older_than_days*
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Data files whose metadata is looked up at once during discovery
+const DISCOVERY_CONCURRENCY: usize = 64;
+
+/// Data files processed between discovery progress reports
+const DISCOVERY_PROGRESS_INTERVAL: usize = 10_000;
 
 /// Discover initial data files in configured *log_dir* to bootstrap
 /// a running Watcher
@@ -19,94 +38,312 @@ pub async fn discover_initial_files(
     config: &WatcherConfig,
     checkpoint: &mut Checkpoint,
     output: &mpsc::Sender<WatcherPayload>,
+    updates: &mpsc::Sender<CheckpointUpdate>,
+    recorder: Option<&EventRecorder>,
 ) -> Result<()> {
-    for entry in build_walker(config).into_iter().filter_map(Result::ok) {
-        let path = entry.path().to_path_buf();
+    discover_files(config, checkpoint, output, updates, recorder).await
+}
 
-        if !valid_file_format(&path) {
-            continue;
+/// Discover new data files in configured *log_dir* to avoid missing
+/// new files when a Watcher is running.
+///
+/// This is intended to handle edge cases where [**notify**](https://docs.rs/notify/latest/notify/index.html) misses filesystem
+/// events and when new data files are discovered.
+pub async fn discover_new_files(
+    config: &WatcherConfig,
+    checkpoint: &mut Checkpoint,
+    output: &mpsc::Sender<WatcherPayload>,
+    updates: &mpsc::Sender<CheckpointUpdate>,
+    recorder: Option<&EventRecorder>,
+) -> Result<()> {
+    discover_files(config, checkpoint, output, updates, recorder).await
+}
+
+async fn discover_files(
+    config: &WatcherConfig,
+    checkpoint: &mut Checkpoint,
+    output: &mpsc::Sender<WatcherPayload>,
+    updates: &mpsc::Sender<CheckpointUpdate>,
+    recorder: Option<&EventRecorder>,
+) -> Result<()> {
+    // walking a large directory tree blocks, keep it off the async workers
+    let entries = {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || walk_data_files(&config)).await??
+    };
+    let total = entries.len();
+
+    // metadata is looked up concurrently, a cold start over tens of thousands of data
+    // files would otherwise take minutes. The Checkpoint is only touched below, one data
+    // file at a time, so the order lookups complete in doesn't matter.
+    let mut lookups = stream::iter(entries)
+        .map(|entry| async move {
+            // metadata follows symlinks, so a symlinked data file is identified
+            // by its target while being tracked under the symlink's path
+            let metadata = tokio::fs::metadata(entry.path()).await;
+
+            (entry, metadata)
+        })
+        .buffer_unordered(DISCOVERY_CONCURRENCY);
+
+    let mut processed = 0;
+
+    while let Some((entry, metadata)) = lookups.next().await {
+        processed += 1;
+
+        if processed % DISCOVERY_PROGRESS_INTERVAL == 0 {
+            info!(processed, total, "Discovering data files");
         }
 
-        let state = determine_file_state(path.clone()).await;
-        let inode = state.inode;
+        // gone since the walk, or a symlink to a directory
+        let Ok(metadata) = metadata else {
+            continue;
+        };
 
-        if let Some(existing) = checkpoint.files.get(&inode) {
+        if metadata.is_dir() {
             continue;
         }
 
-        checkpoint.files.insert(inode, state);
+        let path = entry.path().to_path_buf();
+        let mut state = file_state(path.clone(), &metadata);
+        let id = state.id();
+        let excluded = excluded_by_policy(config, &metadata);
+        let moved = match checkpoint.files.get(&id) {
+            Some(tracked) if tracked.path != path && !tracked.retired && !tracked.skipped => {
+                !is_at(id, &tracked.path).await
+            }
+            _ => false,
+        };
+
+        let event = match checkpoint.files.get(&id) {
+            // a skipped data file the limits no longer exclude, e.g., because it was
+            // written to again, is tailed from where it was skipped, or from the start
+            // when backfilling
+            Some(tracked) if tracked.skipped && excluded.is_none() => {
+                if !config.backfill.unwrap_or(false) {
+                    state.offset = tracked.offset;
+                }
+
+                debug!(path = %path.display(), offset = state.offset, "Picking up skipped data file");
+                WatcherEvent::FileDiscovered {
+                    id,
+                    path: path.clone(),
+                }
+            }
+            // a retired data file that is still written to, e.g., renamed to a name that
+            // still matches while its writer kept appending to it, is tailed again from
+            // where it was retired
+            Some(tracked) if tracked.retired && metadata.len() > tracked.offset => {
+                state.offset = tracked.offset;
 
-        let payload = WatcherPayload {
-            inode,
-            path: path.clone(),
-            event: WatcherEvent::FileDiscovered { inode, path },
+                debug!(path = %path.display(), offset = state.offset, "Picking up retired data file that grew");
+                WatcherEvent::FileDiscovered {
+                    id,
+                    path: path.clone(),
+                }
+            }
+            // renamed while it wasn't watched, e.g., while the Core Agent was down, its
+            // Tailer continues at the new path from the tracked offset
+            Some(tracked) if moved => {
+                state.offset = tracked.offset;
+
+                debug!(from = %tracked.path.display(), to = %path.display(), "Data file was renamed");
+                WatcherEvent::FileDiscovered {
+                    id,
+                    path: path.clone(),
+                }
+            }
+            Some(_) => continue,
+            None => match retargeted_symlink(checkpoint, &entry, &path) {
+                Some(old_id) => WatcherEvent::FileRotated {
+                    old_id,
+                    new_id: id,
+                    old_path: path.clone(),
+                    new_path: path.clone(),
+                },
+                None => {
+                    // tracked at its end, so it isn't checked again on every rescan and
+                    // its history isn't read once it is written to again
+                    if let Some(reason) = excluded {
+                        debug!(path = %path.display(), reason, "Skipping data file");
+                        state.offset = metadata.len();
+                        state.skipped = true;
+                    }
+
+                    WatcherEvent::FileDiscovered {
+                        id,
+                        path: path.clone(),
+                    }
+                }
+            },
         };
 
-        output.send(payload).await?;
+        if let Some(recorder) = recorder {
+            recorder.record(
+                Instant::now(),
+                RecordedEvent::Discovered {
+                    root: config.log_dir.clone(),
+                    state: state.clone(),
+                    event: event.clone(),
+                },
+            );
+        }
+
+        track(checkpoint, output, updates, state, event).await?;
+    }
+
+    if total >= DISCOVERY_PROGRESS_INTERVAL {
+        info!(total, "Discovered data files");
     }
 
     Ok(())
 }
 
-/// Discover new data files in configured *log_dir* to avoid missing
-/// new files when a Watcher is running.
-///
-/// This is intended to handle edge cases where [**notify**](https://docs.rs/notify/latest/notify/index.html) misses filesystem
-/// events and when new data files are discovered.
-pub async fn discover_new_files(
-    config: &WatcherConfig,
+/// Start tracking a data file discovery picked up and pass it downstream, unless it is
+/// skipped, see `FileState::skipped`. A retargeted symlink, reported as a `FileRotated`
+/// event, replaces the entry of its previous target. A data file that is already tailed
+/// and was found at a new path only has its path updated, keeping its offset.
+pub async fn track(
     checkpoint: &mut Checkpoint,
     output: &mpsc::Sender<WatcherPayload>,
+    updates: &mpsc::Sender<CheckpointUpdate>,
+    state: FileState,
+    event: WatcherEvent,
 ) -> Result<()> {
-    for entry in build_walker(config).into_iter().filter_map(Result::ok) {
-        let path = entry.path().to_path_buf();
+    if let WatcherEvent::FileRotated { old_id, .. } = &event {
+        checkpoint.files.remove(old_id);
+        updates.send(CheckpointUpdate::Remove(*old_id)).await?;
+    }
 
-        if !valid_file_format(&path) {
-            continue;
+    let payload = WatcherPayload {
+        id: state.id(),
+        path: state.path.clone(),
+        offset: state.offset,
+        event,
+    };
+    let skipped = state.skipped;
+
+    match checkpoint.files.get_mut(&state.id()) {
+        Some(tracked) if !tracked.retired && !tracked.skipped => {
+            tracked.path = state.path.clone();
+            tracked.last_seen = state.last_seen;
+
+            updates
+                .send(CheckpointUpdate::Rename {
+                    id: state.id(),
+                    path: state.path,
+                })
+                .await?;
+        }
+        _ => {
+            checkpoint.upsert(state.clone());
+            updates.send(CheckpointUpdate::Upsert(state)).await?;
         }
+    }
+
+    if !skipped {
+        output.send(payload).await?;
+    }
 
-        let state = determine_file_state(path.clone()).await;
-        let inode = state.inode;
+    Ok(())
+}
 
-        if checkpoint.files.contains_key(&inode) {
-            continue;
+/// Walk the configured *log_dir* for the data files a Watcher picks up, see
+/// [`valid_file_name`], or just the data file if *log_dir* is one. Unreadable entries
+/// are skipped. Only file types known from the walk itself are checked, symlinks to
+/// directories are left to the caller, which looks up each data file's metadata anyway.
+pub fn walk_data_files(config: &WatcherConfig) -> Result<Vec<DirEntry>> {
+    let include = config.include_set()?;
+
+    // walking a single data file yields just that data file
+    let single_file = config.is_single_file();
+
+    Ok(build_walker(config)
+        .into_iter()
+        // left to the Watcher of a more specific root
+        .filter_entry(|entry| !config.in_nested_root(entry.path()))
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            single_file
+                || (!entry.file_type().is_dir() && valid_file_name(entry.path(), include.as_ref()))
+        })
+        .collect())
+}
+
+/// Why a data file that isn't tailed yet is skipped under the configured age and size
+/// limits, if it is. Data files already tailed are never skipped, and *backfill* lifts
+/// both limits.
+pub fn excluded_by_policy(config: &WatcherConfig, metadata: &Metadata) -> Option<&'static str> {
+    if config.backfill.unwrap_or(false) {
+        return None;
+    }
+
+    if let Some(max_bytes) = config.ignore_larger_than_bytes {
+        if metadata.len() > max_bytes {
+            return Some("larger than ignore_larger_than_bytes");
         }
+    }
 
-        checkpoint.files.insert(inode, state);
+    if let Some(days) = config.ignore_older_than_days {
+        let max_age = Duration::from_secs(days.saturating_mul(SECS_PER_DAY));
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
 
-        let payload = WatcherPayload {
-            inode,
-            path: path.clone(),
-            event: WatcherEvent::FileDiscovered { inode, path },
-        };
+        if age.is_some_and(|age| age > max_age) {
+            return Some("older than ignore_older_than_days");
+        }
+    }
 
-        output.send(payload).await?;
+    None
+}
+
+/// Whether `path` still points at the data file `id`
+async fn is_at(id: FileId, path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.dev() == id.dev && metadata.ino() == id.inode)
+}
+
+/// A symlink that is already tracked under a different identity has been pointed at a
+/// new target, e.g., `/var/log/containers/*.log` after a container restart. Returns the
+/// identity of the previous target so it can be handled like a rotation.
+fn retargeted_symlink(checkpoint: &Checkpoint, entry: &DirEntry, path: &Path) -> Option<FileId> {
+    if !entry.path_is_symlink() {
+        return None;
     }
 
-    Ok(())
+    checkpoint.find_by_path(path)
 }
 
 fn build_walker(config: &WatcherConfig) -> WalkDir {
+    // walkdir detects symlink loops itself and yields them as errors, which are
+    // skipped like any other unreadable entry
     let mut filesystem_walker = WalkDir::new(&config.log_dir)
-        .follow_links(false)
+        .follow_links(config.follow_symlinks.unwrap_or(false))
         .same_file_system(true);
 
-    if !config.recursive.unwrap_or(true) {
-        filesystem_walker = filesystem_walker.min_depth(0).max_depth(1)
+    if let Some(limit) = config.depth_limit() {
+        filesystem_walker = filesystem_walker.min_depth(0).max_depth(limit)
     }
 
     filesystem_walker
 }
 
-fn valid_file_format(path: &Path) -> bool {
-    if path.is_dir() {
-        return false;
-    }
-
+/// Whether `path` has the file name of a data file a Watcher should pick up. Hidden files
+/// never are, otherwise the file name has to match the configured *include* patterns, or
+/// have a `.log`/`.txt` extension if there are none. Directories are filtered by the
+/// caller, without looking up `path` again.
+pub fn valid_file_name(path: &Path, include: Option<&RegexSet>) -> bool {
     if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
         if file_name.starts_with('.') {
             return false;
         }
+
+        if let Some(include) = include {
+            return include.is_match(file_name);
+        }
     }
 
     matches!(
@@ -114,3 +351,247 @@ fn valid_file_format(path: &Path) -> bool {
         Some("log") | Some("txt")
     )
 }
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::ensure;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Directory of a single test, removed when the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Result<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "ves-discovery-{name}-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&path)?;
+
+            Ok(Self(path))
+        }
+
+        fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+
+        fn config(&self) -> WatcherConfig {
+            WatcherConfig {
+                log_dir: self.0.to_string_lossy().into_owned(),
+                ..WatcherConfig::default()
+            }
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Run a rescan of `config` against `checkpoint` and collect what it sent
+    async fn rescan(
+        config: &WatcherConfig,
+        checkpoint: &mut Checkpoint,
+    ) -> Result<(Vec<WatcherPayload>, Vec<CheckpointUpdate>)> {
+        let (output, mut payloads_rx) = mpsc::channel(64);
+        let (updates, mut updates_rx) = mpsc::channel(64);
+
+        discover_new_files(config, checkpoint, &output, &updates, None).await?;
+
+        let mut payloads = Vec::new();
+        while let Ok(payload) = payloads_rx.try_recv() {
+            payloads.push(payload);
+        }
+
+        let mut sent = Vec::new();
+        while let Ok(update) = updates_rx.try_recv() {
+            sent.push(update);
+        }
+
+        Ok((payloads, sent))
+    }
+
+    /// State of the data file at `path` as it is tracked at `offset`
+    fn tracked(path: &Path, offset: u64) -> Result<FileState> {
+        Ok(FileState {
+            offset,
+            ..file_state(path.to_path_buf(), &fs::metadata(path)?)
+        })
+    }
+
+    fn append(path: &Path, data: &[u8]) -> Result<()> {
+        fs::OpenOptions::new().append(true).open(path)?.write_all(data)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retired_data_file_that_grew_is_tailed_again_from_where_it_was_retired() -> Result<()> {
+        let dir = TestDir::new("retired")?;
+        let log = dir.join("service.log");
+
+        // renamed from app.log and retired once it went idle
+        fs::write(&log, "before retirement\n")?;
+        let retired_at = fs::metadata(&log)?.len();
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(FileState {
+            retired: true,
+            ..tracked(&log, retired_at)?
+        });
+
+        let (payloads, updates) = rescan(&dir.config(), &mut checkpoint).await?;
+        ensure!(
+            payloads.is_empty() && updates.is_empty(),
+            "expected service.log to stay retired while it doesn't grow, got {payloads:?} {updates:?}"
+        );
+
+        append(&log, b"after retirement\n")?;
+
+        let (payloads, updates) = rescan(&dir.config(), &mut checkpoint).await?;
+        ensure!(
+            matches!(
+                payloads.as_slice(),
+                [payload] if payload.path == log && payload.offset == retired_at
+            ),
+            "expected service.log to be tailed again from {retired_at}, got {payloads:?}"
+        );
+        ensure!(
+            matches!(
+                updates.as_slice(),
+                [CheckpointUpdate::Upsert(state)] if !state.retired && state.offset == retired_at
+            ),
+            "expected service.log to no longer be retired, got {updates:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn data_file_renamed_while_not_watched_keeps_its_offset() -> Result<()> {
+        let dir = TestDir::new("renamed")?;
+        let log = dir.join("app.log");
+        let renamed = dir.join("service.log");
+
+        fs::write(&log, "already read\nnot read yet\n")?;
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(tracked(&log, 13)?);
+
+        fs::rename(&log, &renamed)?;
+
+        let (payloads, updates) = rescan(&dir.config(), &mut checkpoint).await?;
+        ensure!(
+            matches!(
+                payloads.as_slice(),
+                [payload] if payload.path == renamed && payload.offset == 13
+            ),
+            "expected service.log to be tailed from the tracked offset, got {payloads:?}"
+        );
+        ensure!(
+            matches!(
+                updates.as_slice(),
+                [CheckpointUpdate::Rename { path, .. }] if *path == renamed
+            ),
+            "expected app.log to be renamed in the Checkpoint, got {updates:?}"
+        );
+
+        Ok(())
+    }
+
+    /// Write a data file of `len` bytes last modified `age` ago and return its metadata
+    fn data_file(dir: &TestDir, name: &str, len: usize, age: Duration) -> Result<Metadata> {
+        let path = dir.join(name);
+        fs::write(&path, vec![b'x'; len])?;
+
+        fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() - age)?;
+
+        Ok(fs::metadata(&path)?)
+    }
+
+    #[test]
+    fn size_limit_excludes_only_larger_data_files() -> Result<()> {
+        let dir = TestDir::new("policy-size")?;
+        let config = WatcherConfig {
+            ignore_larger_than_bytes: Some(10),
+            ..dir.config()
+        };
+
+        let at_limit = data_file(&dir, "at-limit.log", 10, Duration::ZERO)?;
+        let larger = data_file(&dir, "larger.log", 11, Duration::ZERO)?;
+
+        ensure!(
+            excluded_by_policy(&config, &at_limit).is_none(),
+            "expected a data file at the limit to be picked up"
+        );
+        ensure!(
+            excluded_by_policy(&config, &larger) == Some("larger than ignore_larger_than_bytes"),
+            "expected a data file past the limit to be excluded"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn age_limit_excludes_only_older_data_files() -> Result<()> {
+        let dir = TestDir::new("policy-age")?;
+        let config = WatcherConfig {
+            ignore_older_than_days: Some(2),
+            ..dir.config()
+        };
+
+        let recent = data_file(&dir, "recent.log", 1, Duration::from_secs(SECS_PER_DAY))?;
+        let old = data_file(&dir, "old.log", 1, Duration::from_secs(3 * SECS_PER_DAY))?;
+
+        ensure!(
+            excluded_by_policy(&config, &recent).is_none(),
+            "expected a data file within the age limit to be picked up"
+        );
+        ensure!(
+            excluded_by_policy(&config, &old) == Some("older than ignore_older_than_days"),
+            "expected a data file past the age limit to be excluded"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn backfill_and_unset_limits_exclude_nothing() -> Result<()> {
+        let dir = TestDir::new("policy-backfill")?;
+        let metadata = data_file(&dir, "old.log", 100, Duration::from_secs(30 * SECS_PER_DAY))?;
+
+        let limited = WatcherConfig {
+            ignore_larger_than_bytes: Some(10),
+            ignore_older_than_days: Some(1),
+            ..dir.config()
+        };
+        let backfill = WatcherConfig {
+            backfill: Some(true),
+            ..limited.clone()
+        };
+
+        ensure!(
+            excluded_by_policy(&dir.config(), &metadata).is_none(),
+            "expected no limits to exclude nothing"
+        );
+        ensure!(
+            excluded_by_policy(&backfill, &metadata).is_none(),
+            "expected backfill to lift both limits"
+        );
+
+        Ok(())
+    }
+}
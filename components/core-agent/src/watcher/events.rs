@@ -1,5 +1,11 @@
 // Local crates
-use crate::watcher::models::WatcherEvent;
+use crate::{
+    helpers::load_config::WatcherConfig,
+    watcher::{
+        discovery::excluded_by_policy,
+        models::{Checkpoint, EventPaths, FileId, PathInfo, RenameTracker, WatcherEvent},
+    },
+};
 
 // External crates
 use notify::{
@@ -8,58 +14,266 @@ use notify::{
 };
 use std::fs;
 use std::os::unix::fs::MetadataExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+/// Time the "to" half of a rename has to arrive after its "from" half. A "from" without
+/// a matching "to" means the data file was moved out of the watched directory.
+const RENAME_PAIR_TIMEOUT: Duration = Duration::from_secs(1);
 
-fn inode_for(path: &Path) -> Option<u64> {
-    fs::metadata(path).ok().map(|m| m.ino())
+/// Identity of a data file at a path that may no longer exist, e.g., after it was renamed
+/// or unlinked, falling back to the identity it is tracked under in the `Checkpoint`
+fn tracked_id_for(path: &Path, paths: &EventPaths, checkpoint: &Checkpoint) -> Option<FileId> {
+    checkpoint.find_by_path(path).or_else(|| paths.id(path))
 }
 
-/// Translate notify EventKinds in configured *log_dir* to a `WatcherEvent` type
-pub fn translate_event(event: Event) -> Vec<WatcherEvent> {
-    let mut out = Vec::new();
+impl EventPaths {
+    /// Look up every path of `event` on disk, for a Watcher running with `config`
+    pub fn stat(event: &Event, config: &WatcherConfig) -> Self {
+        Self::stat_paths(&event.paths, config)
+    }
+
+    /// Look up each of `paths` on disk, for a Watcher running with `config`
+    pub fn stat_paths<'a>(
+        paths: impl IntoIterator<Item = &'a PathBuf>,
+        config: &WatcherConfig,
+    ) -> Self {
+        let paths = paths
+            .into_iter()
+            .filter_map(|path| {
+                // metadata follows symlinks, so a symlinked data file is identified by
+                // its target
+                let metadata = fs::metadata(path).ok()?;
+                let symlink = fs::symlink_metadata(path)
+                    .map(|metadata| metadata.file_type().is_symlink())
+                    .unwrap_or(false);
+
+                Some(PathInfo {
+                    path: path.clone(),
+                    id: FileId {
+                        dev: metadata.dev(),
+                        inode: metadata.ino(),
+                    },
+                    symlink,
+                    dir: metadata.is_dir(),
+                    len: metadata.len(),
+                    excluded: excluded_by_policy(config, &metadata).is_some(),
+                })
+            })
+            .collect();
+
+        Self { paths }
+    }
+
+    fn get(&self, path: &Path) -> Option<&PathInfo> {
+        self.paths.iter().find(|info| info.path == path)
+    }
+
+    /// Identity of the file at `path`, if it existed
+    pub fn id(&self, path: &Path) -> Option<FileId> {
+        self.get(path).map(|info| info.id)
+    }
+
+    /// Whether `path` was a symlink
+    pub fn is_symlink(&self, path: &Path) -> bool {
+        self.get(path).is_some_and(|info| info.symlink)
+    }
+
+    /// Whether `path` was a directory
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.get(path).is_some_and(|info| info.dir)
+    }
+
+    /// Size of the file at `path` if the age and size limits exclude it
+    pub fn excluded(&self, path: &Path) -> Option<u64> {
+        self.get(path)
+            .filter(|info| info.excluded)
+            .map(|info| info.len)
+    }
+}
+
+impl RenameTracker {
+    /// Look up the "from" paths still waiting for their "to" half on disk, so
+    /// [`expire_renames`] can tell whether a data file came back to its path
+    pub fn stat_pending(&self, config: &WatcherConfig) -> EventPaths {
+        EventPaths::stat_paths(self.pending.values().map(|(path, _)| path), config)
+    }
+
+    /// Remember the "from" half of a rename until its "to" half arrives
+    fn rename_from(&mut self, cookie: usize, path: PathBuf, now: Instant) {
+        self.pending.insert(cookie, (path, now));
+    }
+
+    /// Take the "from" path matching the "to" half of a rename
+    fn rename_to(&mut self, cookie: usize) -> Option<PathBuf> {
+        self.pending.remove(&cookie).map(|(path, _)| path)
+    }
+
+    /// Take the "from" paths whose "to" half never arrived by `now`
+    fn expired(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut expired = Vec::new();
+
+        self.pending.retain(|_, (path, since)| {
+            if now.saturating_duration_since(*since) < RENAME_PAIR_TIMEOUT {
+                return true;
+            }
+
+            expired.push(std::mem::take(path));
+            false
+        });
+
+        expired
+    }
+}
+
+/// Translate notify EventKinds in configured *log_dir* to a `WatcherEvent` type.
+///
+/// Renames are tracked across events: backends like inotify report a rename as a "from"
+/// and a "to" event sharing a cookie, which are paired up in `renames`. A rename within
+/// *log_dir* is a `FileRotated` event from the old path to the new one. The data file
+/// keeps its identity, it was only renamed, e.g., `app.log` -> `app.log.1` by logrotate.
+/// A data file moved out of *log_dir* is removed, one moved into it is discovered.
+///
+/// Paths are resolved through `paths`, looked up when the event was received, rather than
+/// the filesystem, and `now` is when it was received, so translating a recorded event
+/// gives the same result as it did live. Paths that no longer exist, e.g., the old path of
+/// a rename or an unlinked data file, are resolved to the identity they are tracked under
+/// in the `Checkpoint`.
+pub fn translate_event(
+    event: Event,
+    paths: &EventPaths,
+    renames: &mut RenameTracker,
+    checkpoint: &Checkpoint,
+    now: Instant,
+) -> Vec<WatcherEvent> {
+    let mut out = expire_renames(renames, paths, checkpoint, now);
+
+    let cookie = event.attrs.tracker();
 
     match event.kind {
         EventKind::Create(CreateKind::File) => {
             for path in event.paths {
-                if let Some(inode) = inode_for(&path) {
-                    out.push(WatcherEvent::FileDiscovered { inode, path });
-                }
+                discovered(path, paths, &mut out);
             }
         }
 
+        // backends pairing the halves of a rename themselves, e.g., inotify, also report
+        // them as "from" and "to" events sharing the cookie, which are handled below
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if cookie.is_some() => {}
+
         EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
-            if event.paths.len() == 2 {
-                let old_path = event.paths[0].clone();
-                let new_path = event.paths[1].clone();
-
-                let old_inode = inode_for(&old_path);
-                let new_inode = inode_for(&new_path);
-
-                if let (Some(old_inode), Some(new_inode)) = (old_inode, new_inode) {
-                    out.push(WatcherEvent::FileRotated {
-                        old_inode,
-                        new_inode,
-                        old_path,
-                        new_path,
-                    });
+            if let [old_path, new_path] = event.paths.as_slice() {
+                renamed(old_path.clone(), new_path.clone(), paths, checkpoint, &mut out);
+            }
+        }
+
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            for path in event.paths {
+                match cookie {
+                    Some(cookie) => renames.rename_from(cookie, path, now),
+                    None => removed(path, paths, checkpoint, &mut out),
                 }
             }
         }
 
-        EventKind::Remove(RemoveKind::File) => {
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            for path in event.paths {
+                match cookie.and_then(|cookie| renames.rename_to(cookie)) {
+                    Some(old_path) => renamed(old_path, path, paths, checkpoint, &mut out),
+                    None => discovered(path, paths, &mut out),
+                }
+            }
+        }
+
+        // backends that can't tell which side of a rename a path is on
+        EventKind::Modify(ModifyKind::Name(_)) => {
             for path in event.paths {
-                // inode may no longer exist, best effort lookup sometimes
-                // metadata still works if file is unlinked but open
-                if let Some(inode) = inode_for(&path) {
-                    out.push(WatcherEvent::FileRemoved { inode, path });
+                if paths.id(&path).is_some() {
+                    discovered(path, paths, &mut out);
+                } else {
+                    removed(path, paths, checkpoint, &mut out);
                 }
             }
         }
 
+        EventKind::Remove(RemoveKind::File) => {
+            for path in event.paths {
+                removed(path, paths, checkpoint, &mut out);
+            }
+        }
+
         other => {
-            // [TODO]: Implement warn!() tracing here
+            trace!(kind = ?other, "Ignoring filesystem event");
         }
     }
 
     out
 }
+
+/// Report data files whose rename "from" half never got its "to" half by `now` as
+/// removed, they were moved out of the watched directory. Called for every filesystem
+/// event and periodically by the Watcher, so this is noticed in a quiet directory too.
+/// Only data files tracked in the `Checkpoint` are reported, and not when `paths` shows
+/// the data file back at its path, e.g., moved out and right back in.
+pub fn expire_renames(
+    renames: &mut RenameTracker,
+    paths: &EventPaths,
+    checkpoint: &Checkpoint,
+    now: Instant,
+) -> Vec<WatcherEvent> {
+    let mut out = Vec::new();
+
+    for path in renames.expired(now) {
+        let returned = checkpoint
+            .find_by_path(&path)
+            .is_some_and(|id| paths.id(&path) == Some(id));
+
+        if !returned {
+            removed(path, &EventPaths::default(), checkpoint, &mut out);
+        }
+    }
+
+    out
+}
+
+fn discovered(path: PathBuf, paths: &EventPaths, out: &mut Vec<WatcherEvent>) {
+    if let Some(id) = paths.id(&path) {
+        out.push(WatcherEvent::FileDiscovered { id, path });
+    }
+}
+
+fn renamed(
+    old_path: PathBuf,
+    new_path: PathBuf,
+    paths: &EventPaths,
+    checkpoint: &Checkpoint,
+    out: &mut Vec<WatcherEvent>,
+) {
+    let Some(new_id) = paths.id(&new_path) else {
+        return;
+    };
+
+    // a data file that wasn't tracked before is new to the Watcher, e.g., a temporary
+    // file atomically renamed into place
+    let Some(old_id) = checkpoint.find_by_path(&old_path) else {
+        out.push(WatcherEvent::FileDiscovered {
+            id: new_id,
+            path: new_path,
+        });
+        return;
+    };
+
+    out.push(WatcherEvent::FileRotated {
+        old_id,
+        new_id,
+        old_path,
+        new_path,
+    });
+}
+
+fn removed(path: PathBuf, paths: &EventPaths, checkpoint: &Checkpoint, out: &mut Vec<WatcherEvent>) {
+    if let Some(id) = tracked_id_for(&path, paths, checkpoint) {
+        out.push(WatcherEvent::FileRemoved { id, path });
+    }
+}
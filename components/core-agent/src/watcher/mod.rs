@@ -1,5 +1,8 @@
+pub mod checkpoint;
 pub mod discovery;
 pub mod events;
 pub mod models;
+pub mod recorder;
 pub mod state;
+pub mod store;
 pub mod watcher;
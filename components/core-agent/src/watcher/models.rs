@@ -1,68 +1,228 @@
 // Local crates
-use crate::helpers::load_config::WatcherConfig;
+use crate::{
+    helpers::load_config::WatcherConfig,
+    watcher::{
+        checkpoint::CheckpointRepr,
+        store::{CheckpointSnapshots, CheckpointUpdate},
+    },
+};
 
 // External crates
+use chrono::{DateTime, Utc};
+use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs::File;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::sync::mpsc;
 
 /// File inode type-aliasing
 pub type Inode = u64;
 
-/// Actual `Watcher` which is responsible for watching the data file configured in *log_dir*
+/// File device ID type-aliasing
+pub type Device = u64;
+
+/// Identity of a data file on the node running the Core Agent. A data file is identified by
+/// the device it lives on and its inode rather than by its path, so renames and rotations
+/// don't confuse which file a `FileState` or `Tailer` belongs to. Inodes are only unique per
+/// device, which is why both are needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: Device,
+    pub inode: Inode,
+}
+
+/// Actual `Watcher` which is responsible for watching the data file configured in *log_dir*.
+///
+/// How far each data file has been read and whether it is retired is reported by its
+/// Tailer to the `CheckpointWriter`, the Watcher picks it up through `snapshots` before
+/// each rescan.
 pub struct Watcher {
     pub config: WatcherConfig,
     pub checkpoint: Checkpoint,
     pub output: mpsc::Sender<WatcherPayload>,
+    pub updates: mpsc::Sender<CheckpointUpdate>,
+    pub renames: RenameTracker,
+    pub recorder: Option<EventRecorder>,
+    pub snapshots: Option<CheckpointSnapshots>,
+}
+
+/// Which of the `WatcherEvent`s translated from filesystem events a `Watcher` passes on.
+/// In single-file mode only events of the data file at `log_path` are, otherwise
+/// discovered data files have to match *include* and the depth limit.
+#[derive(Debug)]
+pub struct EventFilter {
+    pub log_path: PathBuf,
+    pub single_file: bool,
+    pub include: Option<RegexSet>,
+}
+
+/// What the paths of a filesystem event were on disk when the event was handled, looked
+/// up once so translating the event doesn't depend on the filesystem afterwards, see
+/// [`EventRecorder`]. Paths that no longer existed are left out.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventPaths {
+    pub paths: Vec<PathInfo>,
+}
+
+/// A single path of a filesystem event, see `EventPaths`. `id` is the identity of the
+/// file it points to, following symlinks, like every data file is identified. `excluded`
+/// is whether the age and size limits exclude it, see `FileState::skipped`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathInfo {
+    pub path: PathBuf,
+    pub id: FileId,
+    pub symlink: bool,
+    pub dir: bool,
+    pub len: u64,
+    pub excluded: bool,
+}
+
+/// Appends everything a `Watcher` bases its decisions on to a file as NDJSON: the
+/// filesystem events it receives along with their `EventPaths`, the data files discovery
+/// finds, and its ticks. Replaying the file through real `Watcher`s and the
+/// `TailerManager`'s event handling reproduces what they did, without the filesystem.
+///
+/// Every `Watcher` shares the same recorder, entries are tagged with the root they
+/// belong to and timed relative to `started`. Recording stops at the first failed write,
+/// so a full disk doesn't turn into an error per event.
+#[derive(Debug, Clone)]
+pub struct EventRecorder {
+    pub path: PathBuf,
+    pub file: Arc<Mutex<File>>,
+    pub started: Instant,
+    pub stopped: Arc<AtomicBool>,
+}
+
+/// A single line of an event recording, see `EventRecorder`. `at_us` is when it was
+/// recorded, in microseconds since recording started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub at_us: u64,
+    #[serde(flatten)]
+    pub event: RecordedEvent,
+}
+
+/// What a `Watcher` bases its decisions on, see `EventRecorder`. `root` is the *log_dir*
+/// of the Watcher it was recorded by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    /// A Watcher started with `config` and `checkpoint`
+    Started {
+        root: String,
+        config: WatcherConfig,
+        single_file: bool,
+        checkpoint: Checkpoint,
+    },
+    Filesystem {
+        root: String,
+        event: notify::Event,
+        paths: EventPaths,
+    },
+    /// Discovery picked up a data file, see `discovery::track`
+    Discovered {
+        root: String,
+        state: FileState,
+        event: WatcherEvent,
+    },
+    /// Stale `Checkpoint` entries were pruned
+    Pruned { root: String, removed: Vec<FileId> },
+    /// A tick while renames were waiting for their "to" half, with their "from" paths
+    /// looked up on disk
+    Tick {
+        root: String,
+        #[serde(default)]
+        paths: EventPaths,
+    },
+}
+
+/// "from" halves of renames reported by `notify`, keyed by the cookie that pairs them
+/// with their "to" half, and when they were seen
+#[derive(Debug, Default)]
+pub struct RenameTracker {
+    pub pending: HashMap<usize, (PathBuf, Instant)>,
 }
 
 /// Possible translations for received `notify` events from the node(system) running
 /// the Core Agent. This allows events related to the filesystem and tied to the configured
 /// *log_dir* to be understood by the Watcher. These `WatcherEvent`s are then sent to the
 /// `TailerManager` downstream into the pipeline
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum WatcherEvent {
     FileDiscovered {
-        inode: Inode,
+        id: FileId,
         path: PathBuf,
     },
     FileRotated {
-        old_inode: Inode,
-        new_inode: Inode,
+        old_id: FileId,
+        new_id: FileId,
         old_path: PathBuf,
         new_path: PathBuf,
     },
     FileRemoved {
-        inode: Inode,
+        id: FileId,
         path: PathBuf,
     },
 }
 
 /// Current state information for the data file configured in *log_dir*, this state is needed
 /// by the `TailerManager` to determine which `WatcherEvent` is tied to which specific `Tailer`
-/// as well as allow for graceful restarts incase of crashes/restarts
+/// as well as allow for graceful restarts incase of crashes/restarts.
+///
+/// The path is only an attribute of the data file, its identity is `dev` + `inode`. `dev`
+/// defaults to 0 when reading checkpoints written before it was tracked, see
+/// [Checkpoint migration](components/core-agent/src/watcher/checkpoint.rs). `last_seen` is
+/// when the data file was last confirmed to exist, used to expire stale entries.
+///
+/// Together, the entries form the registry of data files being tailed: on startup every
+/// data file that isn't `retired` gets its Tailer back at `offset` right away, without
+/// waiting for the Watcher to rediscover it. A data file is retired once its Tailer
+/// stopped because the data file was rotated away from the path it was tailed under. A
+/// retired data file that is rediscovered and grew since is tailed again at `offset`.
+///
+/// A data file the age and size limits exclude is `skipped`: it is tracked at the end it
+/// had when it was skipped, without a Tailer. Once the limits no longer exclude it, e.g.,
+/// after it was written to again, it is tailed from there rather than from the start.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileState {
     pub path: PathBuf,
+    #[serde(default)]
+    pub dev: Device,
     pub inode: Inode,
     pub offset: u64,
+    #[serde(default = "Utc::now")]
+    pub last_seen: DateTime<Utc>,
+    #[serde(default)]
+    pub retired: bool,
+    #[serde(default)]
+    pub skipped: bool,
 }
 
 /// Stores the exact point in the data file configured in *log_dir* where a running `Watcher` is
 /// at. This uses FileState to determine information about the data file and gracefully restart the
-/// `Watcher`
-#[derive(Debug, Serialize, Deserialize)]
+/// `Watcher`.
+///
+/// Entries are keyed by `FileId`, on disk they are stored as a list, since `FileId` can't be
+/// a map key in formats like JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "CheckpointRepr", into = "CheckpointRepr")]
 pub struct Checkpoint {
-    pub files: HashMap<Inode, FileState>,
+    pub files: HashMap<FileId, FileState>,
 }
 
 /// Payload containing the `WatcherEvent` and `FileState` for the data file configured in *log_dir*.
 /// This payload allows the `TailerManager` to identify the specific `Tailer` tied to the configured
-/// data file.
-#[derive(Clone)]
+/// data file. `offset` is where the Tailer of a discovered data file starts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WatcherPayload {
-    pub inode: u64,
+    pub id: FileId,
     pub path: PathBuf,
+    #[serde(default)]
+    pub offset: u64,
     pub event: WatcherEvent,
 }
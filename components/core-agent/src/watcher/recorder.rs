@@ -0,0 +1,61 @@
+// Local crates
+use crate::watcher::models::{EventRecorder, Recorded, RecordedEvent};
+
+// External crates
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+impl EventRecorder {
+    /// Open the recording at `path`, appending to it if it already exists
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening event recording {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+            started: Instant::now(),
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Append `event`, which happened at `now`, to the recording
+    pub fn record(&self, now: Instant, event: RecordedEvent) {
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let recorded = Recorded {
+            at_us: now.saturating_duration_since(self.started).as_micros() as u64,
+            event,
+        };
+
+        if let Err(e) = self.write(&recorded) {
+            warn!(path = %self.path.display(), error = %e, "Failed to record Watcher event, recording stopped");
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn write(&self, recorded: &Recorded) -> Result<()> {
+        let mut line = serde_json::to_vec(recorded)?;
+        line.push(b'\n');
+
+        // a single write per line, so lines of concurrent Watchers don't interleave
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow!("event recording lock poisoned"))?;
+        file.write_all(&line)?;
+
+        Ok(())
+    }
+}
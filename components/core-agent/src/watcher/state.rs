@@ -2,30 +2,22 @@
 use crate::watcher::models::FileState;
 
 // External crates
+use chrono::Utc;
+use std::fs::Metadata;
 use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 
-pub async fn determine_file_state(path: PathBuf) -> FileState {
-    let metadata = match tokio::fs::metadata(&path).await {
-        Ok(m) => m,
-        Err(e) => {
-            // Return default state
-            return FileState {
-                path: path,
-                inode: 0,
-                offset: 0,
-            };
-        }
-    };
-
-    #[cfg(unix)]
-    let inode = { metadata.ino() };
-
-    let offset = metadata.len();
-
+/// State of a newly discovered data file at `path`, identified by its `metadata`
+pub fn file_state(path: PathBuf, metadata: &Metadata) -> FileState {
+    // a newly discovered data file is read from the start by its Tailer, which reports
+    // its progress, so a restart resumes where the Tailer actually got to
     FileState {
-        path: path.clone(),
-        inode,
-        offset,
+        path,
+        dev: metadata.dev(),
+        inode: metadata.ino(),
+        offset: 0,
+        last_seen: Utc::now(),
+        retired: false,
+        skipped: false,
     }
 }
@@ -0,0 +1,782 @@
+// Local crates
+use crate::{
+    helpers::load_config::WatcherConfig,
+    watcher::models::{Checkpoint, FileId, FileState},
+};
+
+// External crates
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Duration, Instant, MissedTickBehavior, interval},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Default directory for the Core Agent's state when `$XDG_STATE_HOME` isn't set
+const DEFAULT_STATE_DIR: &str = "/var/lib/ves";
+
+/// File name of the Watcher's checkpoint inside the state directory
+const CHECKPOINT_FILE_NAME: &str = "checkpoint.json";
+
+/// How often a degraded `FileCheckpointStore` repeats its warning
+const DEGRADED_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Permissions of state directories created at startup, checkpoints reveal which data
+/// files exist and how far they have been read
+const STATE_DIR_MODE: u32 = 0o750;
+
+/// Default interval between checkpoint writes
+const DEFAULT_CHECKPOINT_FLUSH_INTERVAL_MS: u64 = 2000;
+
+/// Default number of data files with pending updates that forces an early checkpoint write
+const DEFAULT_CHECKPOINT_FLUSH_MAX_UPDATES: usize = 1024;
+
+/// Capacity of the channel carrying snapshot requests to the `CheckpointWriter`
+const SNAPSHOT_REQUESTS_CAPACITY: usize = 16;
+
+/// Durability boundary for a Watcher's `Checkpoint` state. A `CheckpointStore` persists
+/// and recovers checkpoint state across crashes and restarts, the in-memory `Checkpoint`
+/// is only a view of it.
+///
+/// See [ADR: Introducing CheckpointStore for Watcher Durability](components/core-agent/ADR/Watcher/000x-checkpoint-store.md).
+pub trait CheckpointStore: Send + 'static {
+    /// Recover the last persisted `Checkpoint`
+    fn load(&mut self) -> Result<Checkpoint>;
+
+    /// Durably apply a batch of updates, either all updates in the batch are
+    /// persisted or none are
+    fn apply(&mut self, updates: Vec<CheckpointUpdate>) -> Result<()>;
+
+    /// The `Checkpoint` as of the last applied batch
+    fn current(&self) -> &Checkpoint;
+}
+
+/// A single mutation of checkpoint state, sent by the Watcher and Tailers to the
+/// `CheckpointWriter` instead of writing to the `CheckpointStore` themselves.
+#[derive(Debug, Clone)]
+pub enum CheckpointUpdate {
+    Upsert(FileState),
+    Offset { id: FileId, offset: u64 },
+    /// A Tailer stopped for good at `offset`, see `FileState::retired`
+    Retire { id: FileId, offset: u64 },
+    /// A data file was renamed, its offset and whether it is retired stay as they are
+    Rename { id: FileId, path: PathBuf },
+    /// A data file was last seen on disk at `last_seen`, see `FileState::last_seen`
+    Seen { id: FileId, last_seen: DateTime<Utc> },
+    Remove(FileId),
+}
+
+impl CheckpointUpdate {
+    fn id(&self) -> FileId {
+        match self {
+            CheckpointUpdate::Upsert(state) => state.id(),
+            CheckpointUpdate::Offset { id, .. } => *id,
+            CheckpointUpdate::Retire { id, .. } => *id,
+            CheckpointUpdate::Rename { id, .. } => *id,
+            CheckpointUpdate::Seen { id, .. } => *id,
+            CheckpointUpdate::Remove(id) => *id,
+        }
+    }
+}
+
+/// Dedicated task that batches `CheckpointUpdate`s and writes them to a `CheckpointStore`
+/// every `flush_interval`, or as soon as `max_pending` data files have pending updates.
+/// This keeps checkpoint writes out of event handling and turns many small updates,
+/// e.g., offsets from a busy Tailer, into a single write.
+///
+/// The writer is the only one that knows the up-to-date `Checkpoint`, a restarted Watcher
+/// asks it for a snapshot through `CheckpointSnapshots`.
+///
+/// Writing a batch serializes and syncs the whole `Checkpoint`, which blocks, so the
+/// store is shared with the blocking thread pool rather than used on an async worker.
+#[derive(Debug)]
+pub struct CheckpointWriter<S> {
+    pub store: Arc<Mutex<S>>,
+    pub updates_rx: mpsc::Receiver<CheckpointUpdate>,
+    pub flush_interval: Duration,
+    pub max_pending: usize,
+    pub cancel: CancellationToken,
+    pub snapshot_tx: mpsc::Sender<oneshot::Sender<Checkpoint>>,
+    pub snapshot_rx: mpsc::Receiver<oneshot::Sender<Checkpoint>>,
+}
+
+/// Requests snapshots of the `Checkpoint` from a running `CheckpointWriter`, see
+/// [`CheckpointWriter::snapshots`]
+#[derive(Debug, Clone)]
+pub struct CheckpointSnapshots {
+    pub requests: mpsc::Sender<oneshot::Sender<Checkpoint>>,
+}
+
+impl CheckpointSnapshots {
+    /// The `Checkpoint` with every update sent to the `CheckpointWriter` so far applied,
+    /// whether it has been persisted yet or not
+    pub async fn take(&self) -> Result<Checkpoint> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        self.requests
+            .send(reply_tx)
+            .await
+            .map_err(|_| anyhow!("CheckpointWriter stopped"))?;
+
+        reply_rx.await.map_err(|_| anyhow!("CheckpointWriter stopped"))
+    }
+}
+
+impl<S: CheckpointStore> CheckpointWriter<S> {
+    pub fn new(
+        store: S,
+        updates_rx: mpsc::Receiver<CheckpointUpdate>,
+        flush_interval: Duration,
+        max_pending: usize,
+        cancel: CancellationToken,
+    ) -> Self {
+        let (snapshot_tx, snapshot_rx) = mpsc::channel(SNAPSHOT_REQUESTS_CAPACITY);
+
+        Self {
+            store: Arc::new(Mutex::new(store)),
+            updates_rx,
+            flush_interval,
+            max_pending,
+            cancel,
+            snapshot_tx,
+            snapshot_rx,
+        }
+    }
+
+    /// Recover the last persisted `Checkpoint` from the store, before the writer runs
+    pub fn load(&self) -> Result<Checkpoint> {
+        self.store
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .load()
+    }
+
+    /// Handle to request snapshots of the `Checkpoint` while the writer is running
+    pub fn snapshots(&self) -> CheckpointSnapshots {
+        CheckpointSnapshots {
+            requests: self.snapshot_tx.clone(),
+        }
+    }
+
+    /// Running `CheckpointWriter` loop. Pending updates are coalesced per data file, see
+    /// [`coalesce`], only the latest update of each of a data file's fields is written.
+    /// Whatever is pending is flushed one last time when the writer is cancelled or every
+    /// sender is dropped.
+    ///
+    /// A batch the store fails to write stays pending and is retried on the next tick.
+    /// Until a flush succeeds again, the number of pending data files doesn't force early
+    /// flushes, so a full disk isn't retried on every update.
+    pub async fn run(mut self) -> Result<()> {
+        let mut pending: HashMap<FileId, Vec<CheckpointUpdate>> = HashMap::new();
+        let mut ticker = interval(self.flush_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut failing = false;
+
+        loop {
+            tokio::select! {
+                _ = self.cancel.cancelled() => {
+                    break;
+                },
+
+                _ = ticker.tick() => {
+                    failing = !self.flush(&mut pending).await;
+                }
+
+                update = self.updates_rx.recv() => {
+                    let Some(update) = update else {
+                        break;
+                    };
+
+                    coalesce(&mut pending, update);
+
+                    if pending.len() >= self.max_pending && !failing {
+                        failing = !self.flush(&mut pending).await;
+                    }
+                }
+
+                Some(reply) = self.snapshot_rx.recv() => {
+                    // updates sent before the snapshot was requested are part of it
+                    while let Ok(update) = self.updates_rx.try_recv() {
+                        coalesce(&mut pending, update);
+                    }
+
+                    let _ = reply.send(self.snapshot(&pending));
+                }
+            }
+        }
+
+        while let Ok(update) = self.updates_rx.try_recv() {
+            coalesce(&mut pending, update);
+        }
+
+        self.flush(&mut pending).await;
+
+        Ok(())
+    }
+
+    /// The store's `Checkpoint` with the pending updates applied
+    fn snapshot(&self, pending: &HashMap<FileId, Vec<CheckpointUpdate>>) -> Checkpoint {
+        let mut checkpoint = self
+            .store
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .current()
+            .clone();
+
+        for update in pending.values().flatten() {
+            checkpoint.apply(update.clone());
+        }
+
+        checkpoint
+    }
+
+    /// Write the pending updates to the store as a single batch. They are only dropped once
+    /// the store applied them, a failed batch stays pending. Returns whether the flush
+    /// succeeded.
+    async fn flush(&mut self, pending: &mut HashMap<FileId, Vec<CheckpointUpdate>>) -> bool {
+        if pending.is_empty() {
+            return true;
+        }
+
+        let batch: Vec<CheckpointUpdate> = pending.values().flatten().cloned().collect();
+        let count = batch.len();
+
+        let store = self.store.clone();
+        let applied = tokio::task::spawn_blocking(move || {
+            store
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .apply(batch)
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.into()));
+
+        match applied {
+            Ok(()) => {
+                pending.clear();
+                debug!(count, "Flushed Checkpoint updates");
+                true
+            }
+            Err(e) => {
+                error!(count, error = %e, "Failed to flush Checkpoint updates, retrying on the next flush");
+                false
+            }
+        }
+    }
+}
+
+impl CheckpointWriter<FileCheckpointStore> {
+    /// Create a `CheckpointWriter` writing to the checkpoint file configured for a Watcher
+    pub fn from_config(
+        config: &WatcherConfig,
+        updates_rx: mpsc::Receiver<CheckpointUpdate>,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self::new(
+            FileCheckpointStore::new(checkpoint_path(config)),
+            updates_rx,
+            Duration::from_millis(
+                config
+                    .checkpoint_flush_interval_ms
+                    .unwrap_or(DEFAULT_CHECKPOINT_FLUSH_INTERVAL_MS),
+            ),
+            config
+                .checkpoint_flush_max_updates
+                .unwrap_or(DEFAULT_CHECKPOINT_FLUSH_MAX_UPDATES),
+            cancel,
+        )
+    }
+}
+
+/// Location of the checkpoint file of a Watcher, its *checkpoint_path* or the default
+pub fn checkpoint_path(config: &WatcherConfig) -> PathBuf {
+    config
+        .checkpoint_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(default_checkpoint_path)
+}
+
+/// Default location of the checkpoint file, `$XDG_STATE_HOME/ves/checkpoint.json` when
+/// running with an XDG state directory, e.g., as a user service, otherwise
+/// `/var/lib/ves/checkpoint.json`
+fn default_checkpoint_path() -> PathBuf {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("ves"),
+        _ => PathBuf::from(DEFAULT_STATE_DIR),
+    };
+
+    state_dir.join(CHECKPOINT_FILE_NAME)
+}
+
+/// Merge an update into the pending updates of its data file. An upsert or removal
+/// replaces whatever is pending for the data file. Updates of a single field, i.e., an
+/// offset, retirement or rename, are folded into a pending upsert so they aren't lost,
+/// are dropped for a data file pending removal so they don't undo the removal, and
+/// otherwise replace a pending update of the same field.
+fn coalesce(pending: &mut HashMap<FileId, Vec<CheckpointUpdate>>, update: CheckpointUpdate) {
+    let updates = pending.entry(update.id()).or_default();
+
+    if matches!(update, CheckpointUpdate::Upsert(_) | CheckpointUpdate::Remove(_)) {
+        *updates = vec![update];
+        return;
+    }
+
+    match updates.first_mut() {
+        Some(CheckpointUpdate::Upsert(state)) => {
+            match update {
+                CheckpointUpdate::Offset { offset, .. } => state.offset = offset,
+                CheckpointUpdate::Retire { offset, .. } => {
+                    state.offset = offset;
+                    state.retired = true;
+                }
+                CheckpointUpdate::Rename { path, .. } => state.path = path,
+                CheckpointUpdate::Seen { last_seen, .. } => state.last_seen = last_seen,
+                _ => {}
+            }
+            return;
+        }
+        // a late update from a Tailer of a data file that is already gone
+        Some(CheckpointUpdate::Remove(_)) => return,
+        _ => {}
+    }
+
+    match &update {
+        CheckpointUpdate::Offset { offset, .. } => {
+            // an offset doesn't undo a pending retirement
+            for pending_update in updates.iter_mut() {
+                if let CheckpointUpdate::Retire { offset: retired_at, .. } = pending_update {
+                    *retired_at = *offset;
+                    return;
+                }
+            }
+
+            updates.retain(|pending_update| !matches!(pending_update, CheckpointUpdate::Offset { .. }));
+        }
+        CheckpointUpdate::Retire { .. } => updates.retain(|pending_update| {
+            !matches!(
+                pending_update,
+                CheckpointUpdate::Offset { .. } | CheckpointUpdate::Retire { .. }
+            )
+        }),
+        CheckpointUpdate::Rename { .. } => {
+            updates.retain(|pending_update| !matches!(pending_update, CheckpointUpdate::Rename { .. }))
+        }
+        CheckpointUpdate::Seen { .. } => {
+            updates.retain(|pending_update| !matches!(pending_update, CheckpointUpdate::Seen { .. }))
+        }
+        _ => {}
+    }
+
+    updates.push(update);
+}
+
+/// `CheckpointStore` keeping the `Checkpoint` as a file on disk. Each write goes to a
+/// temporary file that is synced and then renamed over the checkpoint file, and the
+/// rename is synced through the checkpoint's directory, so a crash mid-write leaves the
+/// previous checkpoint intact rather than a torn one.
+///
+/// If the checkpoint's directory turns out to be read-only or not writable, the store
+/// degrades to keeping the `Checkpoint` in memory only instead of failing, warning every
+/// `DEGRADED_WARNING_INTERVAL`. Every write still tries the disk first, so the store
+/// recovers on its own once the directory becomes writable again.
+#[derive(Debug)]
+pub struct FileCheckpointStore {
+    pub path: PathBuf,
+    pub checkpoint: Checkpoint,
+    pub degraded: bool,
+    pub last_degraded_warning: Option<Instant>,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            checkpoint: Checkpoint::default(),
+            degraded: false,
+            last_degraded_warning: None,
+        }
+    }
+
+    /// Switch to in-memory-only operation after `error`, warning loudly on the switch and
+    /// periodically while it lasts
+    fn degrade(&mut self, error: &anyhow::Error) {
+        let due = self
+            .last_degraded_warning
+            .is_none_or(|last| last.elapsed() >= DEGRADED_WARNING_INTERVAL);
+
+        if !self.degraded {
+            error!(
+                path = %self.path.display(),
+                error = %error,
+                "Checkpoint path is not writable, keeping the Checkpoint in memory only, \
+                 progress will be lost on restart"
+            );
+        } else if due {
+            warn!(
+                path = %self.path.display(),
+                error = %error,
+                "Checkpoint is still kept in memory only"
+            );
+        } else {
+            return;
+        }
+
+        self.degraded = true;
+        self.last_degraded_warning = Some(Instant::now());
+    }
+
+    fn recover(&mut self) {
+        if self.degraded {
+            info!(path = %self.path.display(), "Checkpoint path is writable again, persisting the Checkpoint");
+            self.degraded = false;
+            self.last_degraded_warning = None;
+        }
+    }
+
+    /// Read the persisted `Checkpoint` without touching the filesystem otherwise, a
+    /// missing checkpoint file is an empty `Checkpoint`
+    pub fn read(&self) -> Result<Checkpoint> {
+        match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("parsing {}", self.path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.path.display())),
+        }
+    }
+
+    fn persist(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        let data = serde_json::to_vec(&self.checkpoint)?;
+
+        let mut file = fs::File::create(&tmp_path)
+            .with_context(|| format!("creating {}", tmp_path.display()))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("renaming {} to {}", tmp_path.display(), self.path.display()))?;
+
+        // the rename itself is only durable once the directory holding it is synced
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .with_context(|| format!("syncing {}", dir.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    /// Missing parent directories of the checkpoint file are created here, at startup,
+    /// rather than failing on the first write
+    fn load(&mut self) -> Result<Checkpoint> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let created = fs::DirBuilder::new()
+                .recursive(true)
+                .mode(STATE_DIR_MODE)
+                .create(dir)
+                .with_context(|| format!("creating {}", dir.display()));
+
+            match created {
+                Ok(()) => {}
+                Err(e) if is_unwritable(&e) => self.degrade(&e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.checkpoint = self.read()?;
+
+        Ok(self.checkpoint.clone())
+    }
+
+    fn apply(&mut self, updates: Vec<CheckpointUpdate>) -> Result<()> {
+        let previous = self.checkpoint.clone();
+
+        for update in updates {
+            self.checkpoint.apply(update);
+        }
+
+        match self.persist() {
+            Ok(()) => self.recover(),
+            Err(e) if is_unwritable(&e) => self.degrade(&e),
+            Err(e) => {
+                // keep the in-memory view in line with what is actually on disk
+                self.checkpoint = previous;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn current(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+}
+
+/// Whether an error means the checkpoint's location can't be written to at all, as
+/// opposed to a transient failure of a single write
+fn is_unwritable(error: &anyhow::Error) -> bool {
+    error
+        .root_cause()
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+
+    // External crates
+    use anyhow::ensure;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const APP_LOG: FileId = FileId { dev: 1, inode: 10 };
+
+    fn state(offset: u64) -> FileState {
+        FileState {
+            path: PathBuf::from("/var/log/app/app.log"),
+            dev: APP_LOG.dev,
+            inode: APP_LOG.inode,
+            offset,
+            last_seen: Utc::now(),
+            retired: false,
+            skipped: false,
+        }
+    }
+
+    /// Pending updates of `APP_LOG` after coalescing `updates` in order
+    fn coalesced(updates: Vec<CheckpointUpdate>) -> Vec<CheckpointUpdate> {
+        let mut pending = HashMap::new();
+
+        for update in updates {
+            coalesce(&mut pending, update);
+        }
+
+        pending.remove(&APP_LOG).unwrap_or_default()
+    }
+
+    #[test]
+    fn upsert_and_remove_replace_pending_updates() -> Result<()> {
+        let upserted = coalesced(vec![
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+            CheckpointUpdate::Retire { id: APP_LOG, offset: 20 },
+            CheckpointUpdate::Upsert(state(0)),
+        ]);
+        ensure!(
+            matches!(
+                upserted.as_slice(),
+                [CheckpointUpdate::Upsert(state)] if state.offset == 0 && !state.retired
+            ),
+            "expected only the upsert to be pending, got {upserted:?}"
+        );
+
+        let removed = coalesced(vec![
+            CheckpointUpdate::Upsert(state(0)),
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+            CheckpointUpdate::Remove(APP_LOG),
+        ]);
+        ensure!(
+            matches!(removed.as_slice(), [CheckpointUpdate::Remove(id)] if *id == APP_LOG),
+            "expected only the removal to be pending, got {removed:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_updates_are_folded_into_a_pending_upsert() -> Result<()> {
+        let pending = coalesced(vec![
+            CheckpointUpdate::Upsert(state(0)),
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+            CheckpointUpdate::Rename {
+                id: APP_LOG,
+                path: PathBuf::from("/var/log/app/app.log.1"),
+            },
+            CheckpointUpdate::Retire { id: APP_LOG, offset: 20 },
+        ]);
+
+        ensure!(
+            matches!(
+                pending.as_slice(),
+                [CheckpointUpdate::Upsert(state)]
+                    if state.offset == 20
+                        && state.retired
+                        && state.path == Path::new("/var/log/app/app.log.1")
+            ),
+            "expected a single upsert with every field applied, got {pending:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn offset_does_not_undo_a_pending_retirement() -> Result<()> {
+        let pending = coalesced(vec![
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+            CheckpointUpdate::Retire { id: APP_LOG, offset: 20 },
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 30 },
+        ]);
+
+        ensure!(
+            matches!(pending.as_slice(), [CheckpointUpdate::Retire { offset: 30, .. }]),
+            "expected the retirement to be kept at the latest offset, got {pending:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn late_updates_after_a_removal_are_dropped() -> Result<()> {
+        let pending = coalesced(vec![
+            CheckpointUpdate::Remove(APP_LOG),
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+            CheckpointUpdate::Retire { id: APP_LOG, offset: 20 },
+            CheckpointUpdate::Seen {
+                id: APP_LOG,
+                last_seen: Utc::now(),
+            },
+        ]);
+
+        ensure!(
+            matches!(pending.as_slice(), [CheckpointUpdate::Remove(_)]),
+            "expected only the removal to be pending, got {pending:?}"
+        );
+
+        Ok(())
+    }
+
+    /// Directory of a single test, removed when the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Result<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "ves-{name}-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&path)?;
+
+            Ok(Self(path))
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn store_persists_applied_updates() -> Result<()> {
+        let dir = TestDir::new("checkpoint-store")?;
+        let path = dir.0.join("state/checkpoint.json");
+
+        let mut store = FileCheckpointStore::new(path.clone());
+        let loaded = store.load()?;
+        ensure!(loaded.files.is_empty(), "expected a missing checkpoint to load empty");
+
+        store.apply(vec![
+            CheckpointUpdate::Upsert(state(0)),
+            CheckpointUpdate::Offset { id: APP_LOG, offset: 10 },
+        ])?;
+
+        let reread = FileCheckpointStore::new(path).read()?;
+        ensure!(
+            reread.files.get(&APP_LOG).is_some_and(|state| state.offset == 10),
+            "expected app.log at offset 10 on disk, got {:?}",
+            reread.files
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_keeps_the_previous_checkpoint() -> Result<()> {
+        let dir = TestDir::new("checkpoint-store-failed")?;
+        let path = dir.0.join("checkpoint.json");
+
+        let mut store = FileCheckpointStore::new(path.clone());
+        store.load()?;
+        store.apply(vec![CheckpointUpdate::Upsert(state(10))])?;
+
+        // the temporary file can't be created, which isn't the directory being unwritable
+        fs::create_dir(path.with_extension("tmp"))?;
+
+        let result = store.apply(vec![CheckpointUpdate::Offset { id: APP_LOG, offset: 20 }]);
+
+        ensure!(
+            result.is_err() && !store.degraded,
+            "expected the write to fail, got {result:?}"
+        );
+        ensure!(
+            store.current().files.get(&APP_LOG).is_some_and(|state| state.offset == 10),
+            "expected the in-memory Checkpoint to match the disk, got {:?}",
+            store.current().files
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn degraded_store_recovers_once_a_write_succeeds() -> Result<()> {
+        let dir = TestDir::new("checkpoint-store-degraded")?;
+        let path = dir.0.join("checkpoint.json");
+
+        let mut store = FileCheckpointStore::new(path.clone());
+        store.load()?;
+
+        let unwritable = anyhow::Error::from(std::io::Error::from(ErrorKind::ReadOnlyFilesystem))
+            .context("creating checkpoint.tmp");
+        ensure!(is_unwritable(&unwritable), "expected a read-only filesystem to be unwritable");
+
+        store.degrade(&unwritable);
+        ensure!(store.degraded, "expected the store to be degraded");
+
+        store.apply(vec![CheckpointUpdate::Upsert(state(10))])?;
+
+        ensure!(
+            !store.degraded && store.last_degraded_warning.is_none(),
+            "expected the store to recover"
+        );
+        ensure!(
+            FileCheckpointStore::new(path).read()?.files.contains_key(&APP_LOG),
+            "expected the Checkpoint to be persisted after recovering"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_permission_and_read_only_errors_are_unwritable() -> Result<()> {
+        let transient = anyhow::Error::from(std::io::Error::from(ErrorKind::StorageFull));
+        let denied = anyhow::Error::from(std::io::Error::from(ErrorKind::PermissionDenied));
+
+        ensure!(
+            !is_unwritable(&transient) && is_unwritable(&denied),
+            "expected only a denied write to be unwritable"
+        );
+
+        Ok(())
+    }
+}
@@ -2,90 +2,290 @@
 use crate::{
     helpers::load_config::WatcherConfig,
     watcher::{
+        checkpoint::PruneReport,
         discovery::*,
         events::*,
-        models::{Checkpoint, FileState, Watcher, WatcherEvent, WatcherPayload},
+        models::{
+            Checkpoint, EventFilter, EventPaths, EventRecorder, FileState, RecordedEvent,
+            RenameTracker, Watcher, WatcherEvent, WatcherPayload,
+        },
+        store::{CheckpointSnapshots, CheckpointUpdate},
     },
 };
 
 // External crates
 use anyhow::Result;
+use chrono::Utc;
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Instant;
 use tokio::{
     sync::{broadcast, mpsc},
     time::{Duration, interval},
 };
 use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Default time a `Checkpoint` entry is kept after its data file disappeared
+const DEFAULT_CHECKPOINT_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// How often a running Watcher prunes stale `Checkpoint` entries
+const CHECKPOINT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 impl Watcher {
     pub fn new(
         config: WatcherConfig,
         checkpoint: Checkpoint,
         output: mpsc::Sender<WatcherPayload>,
+        updates: mpsc::Sender<CheckpointUpdate>,
+        recorder: Option<EventRecorder>,
+        snapshots: Option<CheckpointSnapshots>,
     ) -> Self {
         Self {
             config,
             checkpoint,
             output,
+            updates,
+            renames: RenameTracker::default(),
+            recorder,
+            snapshots,
+        }
+    }
+
+    /// Append `event` to the event recording, if recording is enabled. `event` is only
+    /// built when it is.
+    fn record_event(&self, now: Instant, event: impl FnOnce() -> RecordedEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(now, event());
+        }
+    }
+
+    /// Hand a `Checkpoint` mutation to the `CheckpointWriter`, which persists it in
+    /// its next batch
+    async fn record(&self, update: CheckpointUpdate) -> Result<()> {
+        self.updates.send(update).await?;
+        Ok(())
+    }
+
+    /// Take the offsets and retirements Tailers reported to the `CheckpointWriter` into the
+    /// Watcher's view of the data files it tracks, so a rescan sees which of them are
+    /// retired and tails them again from where they were retired if they grow.
+    async fn refresh_checkpoint(&mut self) -> Result<()> {
+        let Some(snapshots) = &self.snapshots else {
+            return Ok(());
+        };
+
+        let latest = snapshots.take().await?;
+
+        for (id, state) in &mut self.checkpoint.files {
+            if let Some(latest) = latest.files.get(id) {
+                state.offset = latest.offset;
+                state.retired = latest.retired;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A `FileDiscovered` event for a symlink that is already tracked under a different
+    /// identity means the symlink was pointed at a new target, which is handled like a
+    /// rotation from the previous target to the new one
+    fn resolve_retargeted_symlink(&self, event: WatcherEvent, paths: &EventPaths) -> WatcherEvent {
+        if let WatcherEvent::FileDiscovered { id, path } = &event {
+            if let Some(old_id) = self.checkpoint.find_by_path(path) {
+                if paths.is_symlink(path) && old_id != *id {
+                    return WatcherEvent::FileRotated {
+                        old_id,
+                        new_id: *id,
+                        old_path: path.clone(),
+                        new_path: path.clone(),
+                    };
+                }
+            }
         }
+
+        event
     }
 
-    fn build_payload(&mut self, event: WatcherEvent) -> Option<WatcherPayload> {
+    async fn build_payload(
+        &mut self,
+        event: WatcherEvent,
+        paths: &EventPaths,
+    ) -> Result<Option<WatcherPayload>> {
+        let event = self.resolve_retargeted_symlink(event, paths);
+
         match &event {
-            WatcherEvent::FileDiscovered { inode, path } => {
-                // Insert the data file into Checkpoint
-                self.checkpoint.files.insert(
-                    *inode,
-                    FileState {
-                        inode: *inode,
+            WatcherEvent::FileDiscovered { id, path } => {
+                // a data file that is already tracked keeps its offset and only has its
+                // path refreshed, a new one is inserted into the Checkpoint. Its Tailer
+                // starts at the tracked offset if none is running, e.g., after it stopped
+                // with an error, rather than reading the data file again.
+                let offset = if let Some(state) = self.checkpoint.files.get_mut(id) {
+                    state.path = path.clone();
+                    state.last_seen = Utc::now();
+                    let skipped = state.skipped;
+                    let offset = state.offset;
+
+                    // a retired data file that shows up again, e.g., moved back into place,
+                    // is tailed again and no longer retired
+                    if state.retired {
+                        state.retired = false;
+                        let state = state.clone();
+
+                        self.record(CheckpointUpdate::Upsert(state)).await?;
+                    } else {
+                        self.record(CheckpointUpdate::Rename {
+                            id: *id,
+                            path: path.clone(),
+                        })
+                        .await?;
+                    }
+
+                    // picked up again by discovery once the limits no longer exclude it
+                    if skipped {
+                        return Ok(None);
+                    }
+
+                    offset
+                } else {
+                    // moved into the watched directory, e.g., from an archive, the age
+                    // and size limits apply like to a data file discovery finds
+                    let skipped_at = paths.excluded(path);
+
+                    let state = FileState {
                         path: path.clone(),
-                        offset: 0,
-                    },
-                );
+                        dev: id.dev,
+                        inode: id.inode,
+                        offset: skipped_at.unwrap_or(0),
+                        last_seen: Utc::now(),
+                        retired: false,
+                        skipped: skipped_at.is_some(),
+                    };
+
+                    self.checkpoint.upsert(state.clone());
+                    self.record(CheckpointUpdate::Upsert(state)).await?;
+
+                    if skipped_at.is_some() {
+                        debug!(path = %path.display(), "Skipping data file");
+                        return Ok(None);
+                    }
+
+                    0
+                };
 
-                Some(WatcherPayload {
-                    inode: *inode,
+                Ok(Some(WatcherPayload {
+                    id: *id,
                     path: path.clone(),
+                    offset,
                     event,
-                })
+                }))
             }
 
             WatcherEvent::FileRotated {
-                old_inode,
-                new_inode,
+                old_id,
+                new_id,
                 new_path,
                 ..
             } => {
-                // Update Checkpoint atomically on file rotation
-                self.checkpoint.files.remove(old_inode);
-                self.checkpoint.files.insert(
-                    *new_inode,
-                    FileState {
-                        inode: *new_inode,
+                if old_id == new_id {
+                    // a renamed data file keeps its identity, and with it how far its Tailer
+                    // got and whether it is retired, which only the CheckpointWriter knows,
+                    // so only its path is updated
+                    if let Some(state) = self.checkpoint.files.get_mut(old_id) {
+                        state.path = new_path.clone();
+                        state.last_seen = Utc::now();
+                    }
+
+                    self.record(CheckpointUpdate::Rename {
+                        id: *new_id,
+                        path: new_path.clone(),
+                    })
+                    .await?;
+                } else {
+                    // Update Checkpoint atomically on file rotation
+                    let state = FileState {
                         path: new_path.clone(),
+                        dev: new_id.dev,
+                        inode: new_id.inode,
                         offset: 0,
-                    },
-                );
+                        last_seen: Utc::now(),
+                        retired: false,
+                        skipped: false,
+                    };
+
+                    self.checkpoint.files.remove(old_id);
+                    self.checkpoint.upsert(state.clone());
 
-                Some(WatcherPayload {
-                    inode: *new_inode,
+                    self.record(CheckpointUpdate::Remove(*old_id)).await?;
+                    self.record(CheckpointUpdate::Upsert(state)).await?;
+                }
+
+                Ok(Some(WatcherPayload {
+                    id: *new_id,
                     path: new_path.clone(),
+                    offset: 0,
                     event,
-                })
+                }))
             }
 
-            WatcherEvent::FileRemoved { inode, .. } => {
-                self.checkpoint.files.remove(inode);
+            WatcherEvent::FileRemoved { id, .. } => {
+                self.checkpoint.files.remove(id);
+                self.record(CheckpointUpdate::Remove(*id)).await?;
 
-                Some(WatcherPayload {
-                    inode: *inode,
+                Ok(Some(WatcherPayload {
+                    id: *id,
                     path: PathBuf::new(),
+                    offset: 0,
                     event,
+                }))
+            }
+        }
+    }
+
+    /// Expire and compact `Checkpoint` entries of data files that no longer exist,
+    /// based on the configured checkpoint TTL and entry limit
+    async fn prune_checkpoint(&mut self) -> Result<PruneReport> {
+        let ttl_secs = self
+            .config
+            .checkpoint_ttl_secs
+            .unwrap_or(DEFAULT_CHECKPOINT_TTL_SECS);
+        let max_entries = self.config.checkpoint_max_entries;
+
+        // pruning stats every tracked data file, keep it off the async workers
+        let mut checkpoint = std::mem::take(&mut self.checkpoint);
+        let (checkpoint, report) = tokio::task::spawn_blocking(move || {
+            let report =
+                checkpoint.prune_stale(chrono::Duration::seconds(ttl_secs as i64), max_entries);
+            (checkpoint, report)
+        })
+        .await?;
+
+        self.checkpoint = checkpoint;
+
+        if !report.removed.is_empty() {
+            self.record_event(Instant::now(), || RecordedEvent::Pruned {
+                root: self.config.log_dir.clone(),
+                removed: report.removed.clone(),
+            });
+        }
+
+        for id in &report.removed {
+            self.record(CheckpointUpdate::Remove(*id)).await?;
+        }
+
+        // the TTL counts from the persisted last_seen after a restart
+        for id in &report.seen {
+            if let Some(state) = self.checkpoint.files.get(id) {
+                self.record(CheckpointUpdate::Seen {
+                    id: *id,
+                    last_seen: state.last_seen,
                 })
+                .await?;
             }
         }
+
+        Ok(report)
     }
 
     // running Watcher loop
@@ -94,6 +294,12 @@ impl Watcher {
         mut shutdown_rx: broadcast::Receiver<()>,
         cancel: CancellationToken,
     ) -> Result<()> {
+        if !Path::new(&self.config.log_dir).exists()
+            && !self.wait_for_log_dir(&mut shutdown_rx, &cancel).await?
+        {
+            return Ok(());
+        }
+
         // channel to notify Watcher of FileSystem events
         let (fs_tx, mut fs_rx) = mpsc::channel::<Event>(128);
 
@@ -104,24 +310,68 @@ impl Watcher {
                     let _ = fs_tx.blocking_send(event);
                 }
             },
-            notify::Config::default(),
+            notify::Config::default()
+                .with_follow_symlinks(self.config.follow_symlinks.unwrap_or(false)),
         )?;
 
-        let recursive = self.config.recursive.unwrap_or(true);
+        let log_path = PathBuf::from(&self.config.log_dir);
+        let single_file = self.config.is_single_file();
 
-        watcher.watch(
-            Path::new(&self.config.log_dir),
-            if recursive {
-                RecursiveMode::Recursive
-            } else {
-                RecursiveMode::NonRecursive
-            },
-        )?;
+        if single_file {
+            // watch the data file's directory rather than the data file itself, so the
+            // data file being rotated away and recreated, or created in the first place,
+            // is noticed
+            let dir = match log_path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        } else {
+            // notify can't limit the depth of a recursive watch, events below the depth
+            // limit are filtered instead, see `WatcherConfig::within_depth`
+            let recursive = self.config.depth_limit() != Some(1);
+
+            watcher.watch(
+                &log_path,
+                if recursive {
+                    RecursiveMode::Recursive
+                } else {
+                    RecursiveMode::NonRecursive
+                },
+            )?;
+        }
+
+        let filter = EventFilter::new(&self.config, single_file)?;
+
+        self.record_event(Instant::now(), || RecordedEvent::Started {
+            root: self.config.log_dir.clone(),
+            config: self.config.clone(),
+            single_file,
+            checkpoint: self.checkpoint.clone(),
+        });
+
+        // drop entries left behind by data files removed while the Watcher wasn't running
+        let report = self.prune_checkpoint().await?;
+        info!(
+            expired = report.expired,
+            compacted = report.compacted,
+            remaining = report.remaining,
+            "Pruned {} stale Checkpoint entries on startup",
+            report.pruned()
+        );
 
         // bootstrapping initial data files
-        discover_initial_files(&self.config, &mut self.checkpoint, &self.output).await?;
+        discover_initial_files(
+            &self.config,
+            &mut self.checkpoint,
+            &self.output,
+            &self.updates,
+            self.recorder.as_ref(),
+        ).await?;
 
         let mut ticker = interval(Duration::from_secs(5));
+        let mut prune_ticker = interval(CHECKPOINT_PRUNE_INTERVAL);
 
         // running Watcher loop
         loop {
@@ -135,27 +385,573 @@ impl Watcher {
                 },
 
                 _ = ticker.tick() => {
+                    let paths = self.renames.stat_pending(&self.config);
+                    self.on_tick(&paths, Instant::now(), &filter).await?;
+                    self.refresh_checkpoint().await?;
+
                     // discover new data files while Watcher is running
                     discover_new_files(
                         &self.config,
                         &mut self.checkpoint,
-                        &self.output
+                        &self.output,
+                        &self.updates,
+                        self.recorder.as_ref(),
                     ).await;
                 }
 
+                _ = prune_ticker.tick() => {
+                    let report = self.prune_checkpoint().await?;
+
+                    if report.pruned() > 0 {
+                        info!(
+                            expired = report.expired,
+                            compacted = report.compacted,
+                            remaining = report.remaining,
+                            "Pruned stale Checkpoint entries"
+                        );
+                    }
+                }
+
                 Some(event) = fs_rx.recv() => {
-                    let events = translate_event(event);
-
-                    for event in events {
-                        if let Some(payload) = self.build_payload(event) {
-                            if let Err(e) = self.output.send(payload).await {
-                                break;
-                            }
-                        }
+                    let paths = EventPaths::stat(&event, &self.config);
+                    self.on_filesystem_event(event, &paths, Instant::now(), &filter).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for a *log_dir* that doesn't exist yet to appear, watching its nearest existing
+    /// ancestor and moving the watch down as the directories on the way are created.
+    /// Returns `false` when the Watcher is stopped before *log_dir* appears.
+    async fn wait_for_log_dir(
+        &self,
+        shutdown_rx: &mut broadcast::Receiver<()>,
+        cancel: &CancellationToken,
+    ) -> Result<bool> {
+        let log_path = PathBuf::from(&self.config.log_dir);
+
+        let (fs_tx, mut fs_rx) = mpsc::channel::<()>(16);
+        let mut watcher = RecommendedWatcher::new(
+            move |res: notify::Result<Event>| {
+                if res.is_ok() {
+                    let _ = fs_tx.try_send(());
+                }
+            },
+            notify::Config::default(),
+        )?;
+
+        // the watched ancestor can be removed again, which ends its watch, so log_dir is
+        // checked periodically as well
+        let mut ticker = interval(Duration::from_secs(5));
+        let mut watched: Option<PathBuf> = None;
+
+        loop {
+            if log_path.exists() {
+                info!("{} appeared, watching it", self.config.log_dir);
+                return Ok(true);
+            }
+
+            let ancestor = nearest_existing_ancestor(&log_path);
+
+            if watched.as_ref() != Some(&ancestor) {
+                if let Some(previous) = watched.take() {
+                    let _ = watcher.unwatch(&previous);
+                }
+
+                watcher.watch(&ancestor, RecursiveMode::NonRecursive)?;
+                info!(
+                    "{} doesn't exist yet, waiting for it in {}",
+                    self.config.log_dir,
+                    ancestor.display()
+                );
+                watched = Some(ancestor);
+
+                // log_dir may have appeared before the watch was in place
+                continue;
+            }
+
+            tokio::select! {
+                _ = cancel.cancelled() => return Ok(false),
+                Ok(_) = shutdown_rx.recv() => return Ok(false),
+                _ = ticker.tick() => {},
+                Some(()) = fs_rx.recv() => {},
+            }
+        }
+    }
+
+    /// Translate a filesystem event received at `now` and pass on the resulting
+    /// `WatcherEvent`s, see [`translate_event`]
+    pub async fn on_filesystem_event(
+        &mut self,
+        event: Event,
+        paths: &EventPaths,
+        now: Instant,
+        filter: &EventFilter,
+    ) -> Result<()> {
+        self.record_event(now, || RecordedEvent::Filesystem {
+            root: self.config.log_dir.clone(),
+            event: event.clone(),
+            paths: paths.clone(),
+        });
+
+        let events = translate_event(event, paths, &mut self.renames, &self.checkpoint, now);
+        self.dispatch(events, paths, filter).await
+    }
+
+    /// Report data files moved out of the watched directory by `now`, unless `paths`, the
+    /// pending "from" paths looked up on disk, shows them back, see [`expire_renames`]
+    pub async fn on_tick(
+        &mut self,
+        paths: &EventPaths,
+        now: Instant,
+        filter: &EventFilter,
+    ) -> Result<()> {
+        if self.renames.pending.is_empty() {
+            return Ok(());
+        }
+
+        self.record_event(now, || RecordedEvent::Tick {
+            root: self.config.log_dir.clone(),
+            paths: paths.clone(),
+        });
+
+        let events = expire_renames(&mut self.renames, paths, &self.checkpoint, now);
+        self.dispatch(events, &EventPaths::default(), filter).await
+    }
+
+    /// Record translated `WatcherEvent`s in the `Checkpoint` and send the ones `filter`
+    /// passes downstream
+    async fn dispatch(
+        &mut self,
+        events: Vec<WatcherEvent>,
+        paths: &EventPaths,
+        filter: &EventFilter,
+    ) -> Result<()> {
+        for event in events {
+            if !filter.passes(&event, paths, &self.config) {
+                continue;
+            }
+
+            if let Some(payload) = self.build_payload(event, paths).await? {
+                if self.output.send(payload).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EventFilter {
+    /// Filter for a Watcher running with `config`, watching a single data file or not
+    pub fn new(config: &WatcherConfig, single_file: bool) -> Result<Self> {
+        Ok(Self {
+            log_path: PathBuf::from(&config.log_dir),
+            single_file,
+            include: config.include_set()?,
+        })
+    }
+
+    /// Whether `event` is passed on, `paths` are the paths of the filesystem event it was
+    /// translated from
+    fn passes(&self, event: &WatcherEvent, paths: &EventPaths, config: &WatcherConfig) -> bool {
+        if self.single_file {
+            // only the configured data file, not its siblings
+            return concerns(event, &self.log_path);
+        }
+
+        match event {
+            WatcherEvent::FileDiscovered { path, .. } => {
+                !paths.is_dir(path)
+                    && valid_file_name(path, self.include.as_ref())
+                    && config.within_depth(path)
+                    && !config.in_nested_root(path)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Whether a `WatcherEvent` involves the data file at `path`
+fn concerns(event: &WatcherEvent, path: &Path) -> bool {
+    match event {
+        WatcherEvent::FileDiscovered { path: event_path, .. }
+        | WatcherEvent::FileRemoved { path: event_path, .. } => event_path == path,
+        WatcherEvent::FileRotated {
+            old_path, new_path, ..
+        } => old_path == path || new_path == path,
+    }
+}
+
+/// Deepest ancestor of `path` that exists, the current directory when none does
+fn nearest_existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Split `config` into one `WatcherConfig` per watched directory, see
+/// [`WatcherConfig::per_root`], each paired with the entries of `checkpoint` for the data
+/// files under that root. An entry under overlapping roots goes to the most specific one,
+/// whose Watcher is the only one tracking it. Entries under no root go to the first root
+/// so they still get pruned. Every root gets its own `Watcher`, all sharing the same
+/// downstream channel and `CheckpointWriter`.
+pub fn split_by_root(
+    config: &WatcherConfig,
+    mut checkpoint: Checkpoint,
+) -> Result<Vec<(WatcherConfig, Checkpoint)>> {
+    let roots = config.per_root();
+    let mut root_checkpoints = vec![Checkpoint::default(); roots.len()];
+
+    for root in &roots {
+        // fail before spawning anything rather than in a single Watcher
+        root.include_set()?;
+    }
+
+    for (id, state) in checkpoint.files.drain() {
+        let owner = roots
+            .iter()
+            .enumerate()
+            .filter(|(_, root)| state.path.starts_with(&root.log_dir))
+            .max_by_key(|(_, root)| root.log_dir.len())
+            .map(|(index, _)| index)
+            .unwrap_or(0);
+
+        if let Some(root_checkpoint) = root_checkpoints.get_mut(owner) {
+            root_checkpoint.files.insert(id, state);
+        }
+    }
+
+    Ok(roots.into_iter().zip(root_checkpoints).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    // Local crates
+    use super::*;
+    use crate::helpers::load_config::WatchRoot;
+    use crate::watcher::models::FileId;
+
+    // External crates
+    use anyhow::{Context, anyhow, ensure};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::timeout;
+
+    /// Time a test waits for the Watcher to report a filesystem change. Data files moved
+    /// out of the watched directory are only reported on the Watcher's next tick.
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+    /// Directory of a single test, removed when the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Result<Self> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "ves-{name}-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&path)?;
+
+            // notify reports canonical paths, e.g., with the temp dir behind a symlink
+            Ok(Self(fs::canonicalize(&path)?))
+        }
+
+        fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A Watcher running on a `TestDir`, with the receiving ends of its channels
+    struct RunningWatcher {
+        payloads: mpsc::Receiver<WatcherPayload>,
+        updates: mpsc::Receiver<CheckpointUpdate>,
+        discovered: HashSet<(FileId, PathBuf)>,
+        cancel: CancellationToken,
+    }
+
+    impl RunningWatcher {
+        fn start(dir: &TestDir) -> Self {
+            Self::start_at(&dir.0)
+        }
+
+        fn start_at(log_dir: &Path) -> Self {
+            let config = WatcherConfig {
+                log_dir: log_dir.to_string_lossy().into_owned(),
+                ..WatcherConfig::default()
+            };
+
+            let (output, payloads) = mpsc::channel(64);
+            let (updates_tx, updates) = mpsc::channel(64);
+            let (_, shutdown_rx) = broadcast::channel(1);
+            let cancel = CancellationToken::new();
+
+            let watcher =
+                Watcher::new(config, Checkpoint::default(), output, updates_tx, None, None);
+            tokio::spawn(watcher.run(shutdown_rx, cancel.clone()));
+
+            Self {
+                payloads,
+                updates,
+                discovered: HashSet::new(),
+                cancel,
+            }
+        }
+
+        /// Next event the Watcher reports. Discovery and notify may both report a new data
+        /// file, repeated discoveries are skipped.
+        async fn next_event(&mut self) -> Result<WatcherEvent> {
+            loop {
+                let payload = timeout(EVENT_TIMEOUT, self.payloads.recv())
+                    .await
+                    .context("Watcher reported nothing")?
+                    .ok_or_else(|| anyhow!("Watcher stopped"))?;
+
+                if let WatcherEvent::FileDiscovered { id, path } = &payload.event {
+                    if !self.discovered.insert((*id, path.clone())) {
+                        continue;
                     }
                 }
+
+                return Ok(payload.event);
+            }
+        }
+
+        /// `CheckpointUpdate`s the Watcher sent so far
+        fn take_updates(&mut self) -> Vec<CheckpointUpdate> {
+            let mut updates = Vec::new();
+
+            while let Ok(update) = self.updates.try_recv() {
+                updates.push(update);
             }
+
+            updates
         }
+    }
+
+    impl Drop for RunningWatcher {
+        fn drop(&mut self) {
+            self.cancel.cancel();
+        }
+    }
+
+    fn mv(from: &Path, to: &Path) -> Result<()> {
+        let status = Command::new("mv").arg(from).arg(to).status()?;
+        ensure!(status.success(), "mv {} {} failed", from.display(), to.display());
+        Ok(())
+    }
+
+    fn file_id(path: &Path) -> Result<FileId> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(FileId {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+        })
+    }
+
+    #[test]
+    fn overlapping_roots_leave_data_files_to_the_most_specific_root() -> Result<()> {
+        let config = WatcherConfig {
+            log_dir: "/var/log".to_string(),
+            watch_dirs: Some(vec![WatchRoot {
+                path: "/var/log/pods".to_string(),
+                recursive: None,
+                max_depth: None,
+                include: None,
+            }]),
+            ..WatcherConfig::default()
+        };
+
+        let state = |path: &str, inode| FileState {
+            path: PathBuf::from(path),
+            dev: 1,
+            inode,
+            offset: 42,
+            last_seen: Utc::now(),
+            retired: false,
+            skipped: false,
+        };
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.upsert(state("/var/log/syslog.log", 1));
+        checkpoint.upsert(state("/var/log/pods/app/0.log", 2));
+
+        let roots = split_by_root(&config, checkpoint)?;
+
+        let [(var_log, var_log_checkpoint), (pods, pods_checkpoint)] = roots.as_slice() else {
+            return Err(anyhow!("expected two roots, got {}", roots.len()));
+        };
+
+        ensure!(
+            var_log.nested_roots == vec!["/var/log/pods".to_string()] && pods.nested_roots.is_empty(),
+            "expected /var/log/pods to be nested in /var/log only, got {:?} and {:?}",
+            var_log.nested_roots,
+            pods.nested_roots
+        );
+        ensure!(
+            var_log.in_nested_root(Path::new("/var/log/pods/app/0.log"))
+                && !var_log.in_nested_root(Path::new("/var/log/syslog.log")),
+            "expected /var/log to leave only data files under /var/log/pods alone"
+        );
+        ensure!(
+            var_log_checkpoint.files.len() == 1
+                && pods_checkpoint.files.len() == 1
+                && pods_checkpoint.files.values().all(|state| state.offset == 42),
+            "expected each root to get the entries of its own data files, got {var_log_checkpoint:?} and {pods_checkpoint:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logrotate_rename_and_create_rotates_the_data_file() -> Result<()> {
+        let dir = TestDir::new("rotate")?;
+        let log = dir.join("app.log");
+        let rotated = dir.join("app.log.1");
+
+        fs::write(&log, "before rotation\n")?;
+        let id = file_id(&log)?;
+
+        let mut watcher = RunningWatcher::start(&dir);
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileDiscovered { id: found, path } if *found == id && *path == log),
+            "expected app.log to be discovered, got {event:?}"
+        );
+
+        mv(&log, &rotated)?;
+        fs::write(&log, "after rotation\n")?;
+        let new_id = file_id(&log)?;
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(
+                &event,
+                WatcherEvent::FileRotated { old_id, new_id: renamed_id, old_path, new_path }
+                    if *old_id == id && *renamed_id == id && *old_path == log && *new_path == rotated
+            ),
+            "expected app.log to be rotated to app.log.1, got {event:?}"
+        );
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileDiscovered { id: found, path } if *found == new_id && *path == log),
+            "expected the new app.log to be discovered, got {event:?}"
+        );
+
+        // the rotated data file keeps its stored offset, only its path is updated
+        let updates = watcher.take_updates();
+
+        ensure!(
+            updates.iter().any(|update| matches!(
+                update,
+                CheckpointUpdate::Rename { id: renamed_id, path } if *renamed_id == id && *path == rotated
+            )),
+            "expected a rename of app.log in the Checkpoint, got {updates:?}"
+        );
+        ensure!(
+            updates
+                .iter()
+                .filter(|update| matches!(update, CheckpointUpdate::Upsert(state) if state.id() == id))
+                .count()
+                == 1,
+            "expected app.log to be upserted on discovery only, got {updates:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_log_dir_is_watched_once_it_appears() -> Result<()> {
+        let dir = TestDir::new("missing-log-dir")?;
+        let log_dir = dir.join("nested/logs");
+
+        let mut watcher = RunningWatcher::start_at(&log_dir);
+
+        fs::create_dir_all(&log_dir)?;
+        let log = log_dir.join("app.log");
+        fs::write(&log, "line\n")?;
+        let id = file_id(&log)?;
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileDiscovered { id: found, path } if *found == id && *path == log),
+            "expected app.log to be discovered once its directory appeared, got {event:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn data_file_moved_out_is_removed() -> Result<()> {
+        let dir = TestDir::new("move-out")?;
+        let elsewhere = TestDir::new("move-out-target")?;
+        let log = dir.join("app.log");
+
+        fs::write(&log, "line\n")?;
+        let id = file_id(&log)?;
+
+        let mut watcher = RunningWatcher::start(&dir);
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileDiscovered { id: found, .. } if *found == id),
+            "expected app.log to be discovered, got {event:?}"
+        );
+
+        // nothing else happens in the directory, the rename's "from" half expires on the
+        // Watcher's tick
+        mv(&log, &elsewhere.join("app.log"))?;
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileRemoved { id: removed, path } if *removed == id && *path == log),
+            "expected app.log to be removed, got {event:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn data_file_moved_in_is_discovered() -> Result<()> {
+        let dir = TestDir::new("move-in")?;
+        let elsewhere = TestDir::new("move-in-source")?;
+        let outside = elsewhere.join("app.log");
+        let log = dir.join("app.log");
+
+        fs::write(&outside, "line\n")?;
+        let id = file_id(&outside)?;
+
+        let mut watcher = RunningWatcher::start(&dir);
+
+        // give notify time to set up its watch before the move
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        mv(&outside, &log)?;
+
+        let event = watcher.next_event().await?;
+        ensure!(
+            matches!(&event, WatcherEvent::FileDiscovered { id: found, path } if *found == id && *path == log),
+            "expected app.log to be discovered, got {event:?}"
+        );
 
         Ok(())
     }